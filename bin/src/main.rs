@@ -3,21 +3,81 @@ mod server;
 
 use axum::Router;
 use futures::future;
-use tracing::error;
+use std::time::Duration;
+use tracing::{error, warn};
 
 #[tokio::main]
 async fn main() {
 	let config = config();
+	let headers: Vec<Vec<(&str, &str)>> = config
+		.otlp_exporters
+		.iter()
+		.map(|exporter| {
+			exporter
+				.headers
+				.iter()
+				.map(|(key, value)| (key.as_str(), value.as_str()))
+				.collect()
+		})
+		.collect();
+	let exporters: Vec<instrument::TraceExporter> = config
+		.otlp_exporters
+		.iter()
+		.zip(&headers)
+		.map(|(exporter, headers)| instrument::TraceExporter {
+			name: &exporter.name,
+			endpoint: &exporter.endpoint,
+			transport: exporter.transport,
+			headers,
+			batch: exporter.batch.clone(),
+			buffer: config
+				.otlp_buffer
+				.as_deref()
+				.map(|directory| instrument::TraceBuffer {
+					directory,
+					max_bytes: config.otlp_buffer_max_bytes,
+				}),
+		})
+		.collect();
+	let baggage: Vec<&str> = config.baggage.iter().map(String::as_str).collect();
+	let span_metrics: Option<Vec<&str>> = config
+		.span_metrics
+		.as_ref()
+		.map(|labels| labels.iter().map(String::as_str).collect());
+	let histogram_buckets: Option<Vec<&str>> = config
+		.histogram_buckets
+		.as_ref()
+		.map(|names| names.iter().map(String::as_str).collect());
 
 	let _guard = instrument::init(instrument::Options {
 		level: &config.log_level,
 		service: "gollum",
 		version: "0.0.0",
-		exporter: &config.otlp_exporter,
+		exporters: &exporters,
+		propagators: &config.propagators,
+		trace_limits: config.trace_limits,
+		id_generator: config.id_generator,
+		trace_retention: config.trace_retention.clone(),
+		baggage: &baggage,
+		span_metrics: span_metrics.as_deref(),
+		histogram_buckets: histogram_buckets.as_deref(),
+		log_sampling: config.log_sampling,
+		..Default::default()
 	});
 
+	for name in &config.unknown_propagators {
+		warn!(propagator = %name, "skipped unknown propagator");
+	}
+
 	let application = {
-		let router = instrument::http::server::collect_from(router::create());
+		let router = instrument::http::server::collect_from(
+			router::create(),
+			instrument::http::server::Options {
+				trace_headers: config.trace_headers,
+				trust: config.trust.clone(),
+				debug: config.debug.clone(),
+			},
+		);
 
 		Box::pin(server::init(router, 3000))
 	};
@@ -39,14 +99,237 @@ async fn main() {
 
 struct Config {
 	log_level: String,
-	otlp_exporter: String,
+	otlp_exporters: Vec<ExporterConfig>,
+	otlp_buffer: Option<String>,
+	otlp_buffer_max_bytes: u64,
+	propagators: Vec<instrument::Propagator>,
+	unknown_propagators: Vec<String>,
+	trace_limits: instrument::TraceLimits,
+	id_generator: instrument::IdGenerator,
+	trace_retention: Option<instrument::TraceRetention>,
+	baggage: Vec<String>,
+	span_metrics: Option<Vec<String>>,
+	histogram_buckets: Option<Vec<String>>,
+	trace_headers: instrument::http::server::TraceHeaders,
+	trust: instrument::http::server::Trust,
+	debug: instrument::http::server::DebugTrace,
+	log_sampling: f64,
+}
+
+struct ExporterConfig {
+	name: String,
+	endpoint: String,
+	transport: instrument::Transport,
+	headers: Vec<(String, String)>,
+	batch: instrument::TraceBatch,
 }
 
 fn config() -> Config {
 	use std::env::var;
 
+	let (propagators, unknown_propagators) = var("OTEL_PROPAGATORS")
+		.ok()
+		.filter(|names| !names.trim().is_empty())
+		.map(|names| instrument::Propagator::list(&names))
+		.unwrap_or_else(|| (vec![instrument::Propagator::TraceContext], Vec::new()));
+
 	Config {
 		log_level: var("LOG_LEVEL").expect("$LOG_LEVEL is required"),
-		otlp_exporter: var("OTLP_EXPORTER").expect("$OTLP_EXPORTER is required"),
+		otlp_exporters: var("OTLP_EXPORTER")
+			.expect("$OTLP_EXPORTER is required")
+			.split(',')
+			.enumerate()
+			.map(|(index, entry)| match entry.split_once('=') {
+				Some((name, endpoint)) if is_name(name) => exporter(name, endpoint),
+				_ => exporter(&index.to_string(), entry),
+			})
+			.collect(),
+		otlp_buffer: var("OTLP_BUFFER_DIR").ok(),
+		otlp_buffer_max_bytes: number("OTLP_BUFFER_MAX_BYTES").unwrap_or(64 * 1024 * 1024),
+		propagators,
+		unknown_propagators,
+		trace_limits: {
+			let mut limits = instrument::TraceLimits::default();
+			if let Some(count) = number("OTEL_SPAN_ATTRIBUTE_COUNT_LIMIT") {
+				limits.attributes_per_span = count as u32;
+			}
+			if let Some(count) = number("OTEL_SPAN_EVENT_COUNT_LIMIT") {
+				limits.events_per_span = count as u32;
+			}
+			if let Some(count) = number("OTEL_SPAN_LINK_COUNT_LIMIT") {
+				limits.links_per_span = count as u32;
+			}
+			if let Some(count) = number("OTEL_EVENT_ATTRIBUTE_COUNT_LIMIT") {
+				limits.attributes_per_event = count as u32;
+			}
+			if let Some(count) = number("OTEL_LINK_ATTRIBUTE_COUNT_LIMIT") {
+				limits.attributes_per_link = count as u32;
+			}
+			limits.attribute_length =
+				number("OTEL_ATTRIBUTE_VALUE_LENGTH_LIMIT").map(|length| length as usize);
+
+			limits
+		},
+		id_generator: var("TRACE_ID_GENERATOR")
+			.map(|name| {
+				name.parse()
+					.expect("$TRACE_ID_GENERATOR should be random, xray or seeded:<seed>")
+			})
+			.unwrap_or_default(),
+		trace_retention: number("TRACE_RETENTION_WINDOW").map(|millis| {
+			let mut retention = instrument::TraceRetention {
+				window: Duration::from_millis(millis),
+				..Default::default()
+			};
+			if let Some(spans) = number("TRACE_RETENTION_MAX_SPANS") {
+				retention.max_spans = spans as usize;
+			}
+
+			retention
+		}),
+		baggage: var("OTEL_BAGGAGE_FIELDS")
+			.map(|keys| {
+				keys.split(',')
+					.filter(|key| !key.is_empty())
+					.map(String::from)
+					.collect()
+			})
+			.unwrap_or_default(),
+		span_metrics: var("SPAN_METRICS_LABELS").ok().map(|labels| {
+			labels
+				.split(',')
+				.filter(|label| !label.is_empty())
+				.map(String::from)
+				.collect()
+		}),
+		histogram_buckets: var("METRICS_HISTOGRAM_BUCKETS").ok().map(|names| {
+			names
+				.split(',')
+				.filter(|name| !name.is_empty())
+				.map(String::from)
+				.collect()
+		}),
+		trace_headers: {
+			let mut headers = instrument::http::server::TraceHeaders::default();
+			for name in var("TRACE_RESPONSE_HEADERS").unwrap_or_default().split(',') {
+				match name.trim() {
+					"traceresponse" => headers.traceresponse = true,
+					"server-timing" => headers.server_timing = true,
+					"x-trace-id" => headers.trace_id = true,
+					"" => {}
+					_ => panic!("$TRACE_RESPONSE_HEADERS should be a list of traceresponse, server-timing or x-trace-id"),
+				}
+			}
+
+			headers
+		},
+		trust: var("TRACE_TRUST")
+			.map(|policy| {
+				policy.parse().expect(
+					"$TRACE_TRUST should be all, none, header:<name>=<value> or networks:<cidr>,...",
+				)
+			})
+			.unwrap_or_default(),
+		debug: {
+			let mut debug = instrument::http::server::DebugTrace::default();
+			if let Ok(header) = var("DEBUG_TRACE_HEADER") {
+				debug.header = header;
+			}
+			debug.tokens = var("DEBUG_TRACE_TOKENS")
+				.unwrap_or_default()
+				.split(',')
+				.filter(|pair| !pair.trim().is_empty())
+				.map(|pair| {
+					let (owner, token) = pair
+						.split_once('=')
+						.expect("$DEBUG_TRACE_TOKENS should be a list of owner=token");
+
+					(owner.trim().to_string(), token.trim().to_string())
+				})
+				.collect();
+
+			debug
+		},
+		log_sampling: var("LOG_SAMPLING")
+			.map(|ratio| ratio.parse().expect("$LOG_SAMPLING should be a number"))
+			.unwrap_or(1.0),
+	}
+}
+
+fn is_name(name: &str) -> bool {
+	!name.is_empty()
+		&& name
+			.chars()
+			.all(|char| char.is_ascii_alphanumeric() || char == '_' || char == '-')
+}
+
+/// Exporter sending to `endpoint`, set up by the `OTLP_EXPORTER_<NAME>_*` variables or else the
+/// `OTEL_*` ones shared by all exporters
+fn exporter(name: &str, endpoint: &str) -> ExporterConfig {
+	use std::env::var;
+
+	let prefix = format!("OTLP_EXPORTER_{}_", name.to_uppercase().replace('-', "_"));
+	// Name of the variable holding a setting, the exporter's own if it's set
+	let setting = |suffix: &str, shared: &str| {
+		let own = format!("{}{}", prefix, suffix);
+		if var(&own).is_ok() {
+			own
+		} else {
+			shared.to_string()
+		}
+	};
+
+	let protocol = setting("PROTOCOL", "OTEL_EXPORTER_OTLP_PROTOCOL");
+	let transport = match var(&protocol).as_deref() {
+		Ok("http/protobuf") => instrument::Transport::HttpProtobuf,
+		Ok("grpc") | Err(_) => instrument::Transport::Grpc,
+		Ok(_) => panic!("${} should be grpc or http/protobuf", protocol),
+	};
+
+	let headers = setting("HEADERS", "OTEL_EXPORTER_OTLP_HEADERS");
+	let headers = var(&headers)
+		.unwrap_or_default()
+		.split(',')
+		.filter(|pair| !pair.trim().is_empty())
+		.map(|pair| {
+			let (key, value) = pair
+				.split_once('=')
+				.unwrap_or_else(|| panic!("${} should be a list of key=value", headers));
+
+			(key.trim().to_string(), value.trim().to_string())
+		})
+		.collect();
+
+	let mut batch = instrument::TraceBatch::default();
+	if let Some(size) = number(&setting("MAX_QUEUE_SIZE", "OTEL_BSP_MAX_QUEUE_SIZE")) {
+		batch.max_queue_size = size as usize;
 	}
+	if let Some(size) = number(&setting(
+		"MAX_EXPORT_BATCH_SIZE",
+		"OTEL_BSP_MAX_EXPORT_BATCH_SIZE",
+	)) {
+		batch.max_export_batch_size = size as usize;
+	}
+	if let Some(millis) = number(&setting("SCHEDULE_DELAY", "OTEL_BSP_SCHEDULE_DELAY")) {
+		batch.scheduled_delay = Duration::from_millis(millis);
+	}
+	if let Some(millis) = number(&setting("EXPORT_TIMEOUT", "OTEL_BSP_EXPORT_TIMEOUT")) {
+		batch.max_export_timeout = Duration::from_millis(millis);
+	}
+
+	ExporterConfig {
+		name: name.to_string(),
+		endpoint: endpoint.to_string(),
+		transport,
+		headers,
+		batch,
+	}
+}
+
+fn number(name: &str) -> Option<u64> {
+	std::env::var(name).ok().map(|value| {
+		value
+			.parse()
+			.unwrap_or_else(|_| panic!("${} should be a number", name))
+	})
 }
@@ -12,7 +12,7 @@ pub async fn init(router: Router, port: u16) {
 	);
 
 	axum::Server::bind(&addr)
-		.serve(router.into_make_service())
+		.serve(router.into_make_service_with_connect_info::<SocketAddr>())
 		.with_graceful_shutdown(shutdown_signal())
 		.await
 		.expect("server error");
@@ -0,0 +1,39 @@
+use crate::logs::Store;
+use serde::Serialize;
+use serde_json::json;
+use std::future::Future;
+
+tokio::task_local! {
+	static FIELDS: Store;
+}
+
+/// Runs `future` with `fields` added to the `context` of every log line it emits
+///
+/// Fields survive `.await` points and nested calls extend the outer ones, but tasks spawned from
+/// within `future` only inherit them through [`crate::task::spawn`]
+pub async fn with_fields<I, K, V, F>(fields: I, future: F) -> F::Output
+where
+	I: IntoIterator<Item = (K, V)>,
+	K: Into<String>,
+	V: Serialize,
+	F: Future,
+{
+	let mut store = current();
+	for (key, value) in fields {
+		store.insert(key.into(), json!(value));
+	}
+
+	FIELDS.scope(store, future).await
+}
+
+pub(crate) fn current() -> Store {
+	FIELDS.try_with(Store::clone).unwrap_or_default()
+}
+
+pub(crate) async fn inherit<F: Future>(fields: Store, future: F) -> F::Output {
+	FIELDS.scope(fields, future).await
+}
+
+pub(crate) fn inherit_sync<F: FnOnce() -> R, R>(fields: Store, f: F) -> R {
+	FIELDS.sync_scope(fields, f)
+}
@@ -6,3 +6,63 @@ pub fn decorate(builder: ClientBuilder) -> ClientBuilder {
 		.with_init(Extension(OtelName("localhost".into())))
 		.with(TracingMiddleware::default())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::propagation::consumer_span;
+	use crate::testing::{remote_trace_id, traced, TRACEPARENT};
+	use http::HeaderMap;
+	use reqwest_middleware::{Middleware, Next, Result};
+	use std::collections::HashMap;
+	use std::sync::{Arc, Mutex};
+	use task_local_extensions::Extensions;
+	use tracing::Instrument;
+
+	/// Answers in place of the server, keeping the headers it was sent
+	#[derive(Clone, Default)]
+	struct Sent(Arc<Mutex<HeaderMap>>);
+
+	#[axum::async_trait]
+	impl Middleware for Sent {
+		async fn handle(
+			&self,
+			req: reqwest::Request,
+			_extensions: &mut Extensions,
+			_next: Next<'_>,
+		) -> Result<reqwest::Response> {
+			*self.0.lock().unwrap() = req.headers().clone();
+
+			Ok(http::Response::new("").into())
+		}
+	}
+
+	#[test]
+	fn propagates_the_context_of_the_current_span() {
+		let carrier = HashMap::from([
+			("traceparent".to_string(), TRACEPARENT.to_string()),
+			("baggage".to_string(), "tenant.id=acme".to_string()),
+		]);
+		let sent = Sent::default();
+		let client = decorate(ClientBuilder::new(reqwest::Client::new()))
+			.with(sent.clone())
+			.build();
+
+		traced(|| {
+			let runtime = tokio::runtime::Builder::new_current_thread()
+				.enable_all()
+				.build()
+				.unwrap();
+			let request = client.get("http://localhost/").send();
+
+			runtime
+				.block_on(request.instrument(consumer_span("process", &carrier)))
+				.unwrap();
+		});
+
+		let headers = sent.0.lock().unwrap();
+		let traceparent = headers["traceparent"].to_str().unwrap();
+		assert!(traceparent.contains(&remote_trace_id().to_string()));
+		assert_eq!(headers["baggage"], "tenant.id=acme");
+	}
+}
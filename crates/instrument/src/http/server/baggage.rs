@@ -0,0 +1,87 @@
+use super::trust::Remote;
+use axum::extract::{FromRequest, RequestParts};
+use opentelemetry::baggage::BaggageExt;
+use std::convert::Infallible;
+
+/// Baggage sent along with the incoming request
+///
+/// Only available when the `baggage` propagator is enabled and the client is trusted, otherwise
+/// it's always empty
+#[derive(Clone, Debug, Default)]
+pub struct Baggage(Vec<(String, String)>);
+
+impl Baggage {
+	pub fn get(&self, key: &str) -> Option<&str> {
+		self.0
+			.iter()
+			.find(|(name, _)| name == key)
+			.map(|(_, value)| value.as_str())
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+		self.0
+			.iter()
+			.map(|(name, value)| (name.as_str(), value.as_str()))
+	}
+}
+
+#[axum::async_trait]
+impl<B: Send> FromRequest<B> for Baggage {
+	type Rejection = Infallible;
+
+	async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+		let entries = match req.extensions().get::<Remote>() {
+			Some(remote) => remote
+				.context
+				.baggage()
+				.iter()
+				.map(|(key, (value, _))| (key.to_string(), value.to_string()))
+				.collect(),
+			None => Vec::new(),
+		};
+
+		Ok(Baggage(entries))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::http::server::Trust;
+	use http::Request;
+
+	fn extract(trust: Trust, mut req: Request<()>) -> Baggage {
+		let remote = Remote::of(&trust, &req);
+		req.extensions_mut().insert(remote);
+		let mut parts = RequestParts::new(req);
+
+		futures::executor::block_on(Baggage::from_request(&mut parts)).unwrap()
+	}
+
+	#[test]
+	fn reads_the_incoming_baggage() {
+		crate::testing::propagate();
+
+		let req = || {
+			Request::builder()
+				.header("baggage", "tenant.id=acme,feature.flag=on")
+				.body(())
+				.unwrap()
+		};
+
+		let baggage = extract(Trust::All, req());
+		assert_eq!(baggage.get("tenant.id"), Some("acme"));
+		assert_eq!(baggage.get("feature.flag"), Some("on"));
+		assert_eq!(baggage.get("region"), None);
+		assert_eq!(extract(Trust::All, Request::new(())).iter().count(), 0);
+
+		assert_eq!(
+			extract(Trust::None, req()).iter().count(),
+			0,
+			"untrusted clients send no baggage"
+		);
+		let mut parts = RequestParts::new(req());
+		let unresolved = futures::executor::block_on(Baggage::from_request(&mut parts)).unwrap();
+		assert_eq!(unresolved.iter().count(), 0, "nor do unresolved requests");
+	}
+}
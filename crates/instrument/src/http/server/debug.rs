@@ -0,0 +1,79 @@
+use super::secret;
+use http::Request;
+
+/// Lets requests carrying one of the `tokens` in `header` be sampled and logged at `debug`
+///
+/// Tokens are pairs of owner and secret, the owner is what shows up in the audit lines. Records
+/// from the `log` crate stay at the configured level
+#[derive(Clone, Debug)]
+pub struct DebugTrace {
+	pub header: String,
+	pub tokens: Vec<(String, String)>,
+}
+
+impl Default for DebugTrace {
+	fn default() -> Self {
+		DebugTrace {
+			header: String::from("x-debug-trace"),
+			tokens: Vec::new(),
+		}
+	}
+}
+
+pub(super) enum Verdict<'a> {
+	Granted { owner: &'a str },
+	Rejected,
+}
+
+impl DebugTrace {
+	pub(super) fn enabled(&self) -> bool {
+		!self.tokens.is_empty()
+	}
+
+	/// Owner of the token sent along with `req`, if it asked for debugging at all
+	pub(super) fn check<B>(&self, req: &Request<B>) -> Option<Verdict<'_>> {
+		if !self.enabled() {
+			return None;
+		}
+
+		let sent = req.headers().get(self.header.as_str())?.as_bytes();
+		let verdict = self
+			.tokens
+			.iter()
+			.find(|(_, token)| secret::same(token.as_bytes(), sent))
+			.map_or(Verdict::Rejected, |(owner, _)| Verdict::Granted { owner });
+
+		Some(verdict)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn grants_known_tokens_only() {
+		let debug = DebugTrace {
+			tokens: vec![(String::from("alice"), String::from("s3cret"))],
+			..Default::default()
+		};
+
+		let request = |token: &str| {
+			Request::builder()
+				.header("x-debug-trace", token)
+				.body(())
+				.unwrap()
+		};
+
+		assert!(matches!(
+			debug.check(&request("s3cret")),
+			Some(Verdict::Granted { owner: "alice" })
+		));
+		assert!(matches!(
+			debug.check(&request("s3cre")),
+			Some(Verdict::Rejected)
+		));
+		assert!(debug.check(&Request::new(())).is_none());
+		assert!(DebugTrace::default().check(&request("s3cret")).is_none());
+	}
+}
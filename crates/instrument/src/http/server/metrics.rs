@@ -1,20 +1,40 @@
-use crate::metrics::HANDLE;
+use crate::metrics;
 
+use axum::http::{header, HeaderMap, HeaderValue};
+use axum::response::IntoResponse;
 use axum::{routing::get, Router};
 use axum_prometheus::PrometheusMetricLayer;
 
+const OPENMETRICS: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
 pub fn layer() -> PrometheusMetricLayer {
 	PrometheusMetricLayer::new()
 }
 
 pub fn route(router: Router) -> Router {
-	router.route(
-		"/metrics",
-		get(|| async {
-			HANDLE
-				.get()
-				.expect("Should have initialized metrics module first")
-				.render()
-		}),
-	)
+	router.route("/metrics", get(render))
+}
+
+// Exemplars only exist in OpenMetrics, so they're left out unless the scraper asks for it
+async fn render(headers: HeaderMap) -> impl IntoResponse {
+	let openmetrics = headers
+		.get_all(header::ACCEPT)
+		.iter()
+		.filter_map(|value| value.to_str().ok())
+		.any(|value| value.contains("application/openmetrics-text"));
+
+	if openmetrics {
+		(
+			[(header::CONTENT_TYPE, HeaderValue::from_static(OPENMETRICS))],
+			metrics::render(true),
+		)
+	} else {
+		(
+			[(
+				header::CONTENT_TYPE,
+				HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8"),
+			)],
+			metrics::render(false),
+		)
+	}
 }
@@ -1,15 +1,54 @@
+mod baggage;
+mod debug;
 mod metrics;
+mod response;
+mod secret;
 mod traces;
+mod trust;
 
+pub use self::baggage::Baggage;
+pub use self::debug::DebugTrace;
+pub use self::response::TraceHeaders;
+pub use self::trust::{Network, Trust};
+
+use axum::middleware;
 use axum::Router;
+use std::sync::Arc;
 use tower::ServiceBuilder;
 
-pub fn collect_from(router: Router) -> Router {
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+	/// Trace context written to every response, none by default
+	pub trace_headers: TraceHeaders,
+	/// Clients whose trace context and baggage are followed, everyone by default
+	pub trust: Trust,
+	/// Tokens allowing requests to ask for debug logs, none by default
+	pub debug: DebugTrace,
+}
+
+pub fn collect_from(router: Router, opts: Options) -> Router {
 	let metrics_layer = self::metrics::layer();
-	let traces_layer = self::traces::layer();
+	if opts.debug.enabled() {
+		crate::logs::enable_debug();
+	}
+	let trust = Arc::new(opts.trust);
+	let traces_layer = self::traces::layer(opts.debug);
+
+	// Goes under the trace layer so that the request span is current
+	let trace_headers = opts.trace_headers;
+	let router = if trace_headers.any() {
+		router.layer(middleware::from_fn(move |req, next| {
+			self::response::inject(trace_headers, req, next)
+		}))
+	} else {
+		router
+	};
 
 	router.layer(
 		ServiceBuilder::new()
+			.layer(middleware::from_fn(move |req, next| {
+				self::trust::resolve(trust.clone(), req, next)
+			}))
 			.layer(traces_layer)
 			.layer(metrics_layer),
 	)
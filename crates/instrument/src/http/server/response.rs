@@ -0,0 +1,130 @@
+use axum::middleware::Next;
+use axum::response::Response;
+use http::{HeaderValue, Request};
+use opentelemetry::trace::TraceContextExt;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Headers telling clients which trace served their request, e.g. to quote it in bug reports
+///
+/// Browsers only expose them to cross-origin scripts listed in `Access-Control-Expose-Headers`,
+/// and `Server-Timing` also needs `Timing-Allow-Origin`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TraceHeaders {
+	/// W3C `traceresponse`, with the trace and server span ids
+	pub traceresponse: bool,
+	/// `Server-Timing: traceparent;desc="..."`, readable through the Resource Timing API
+	pub server_timing: bool,
+	/// `x-trace-id` with only the trace id
+	pub trace_id: bool,
+}
+
+impl TraceHeaders {
+	pub fn any(&self) -> bool {
+		self.traceresponse || self.server_timing || self.trace_id
+	}
+}
+
+/// Adds the enabled headers for the request span, which must be current
+pub async fn inject<B>(headers: TraceHeaders, req: Request<B>, next: Next<B>) -> Response {
+	let mut response = next.run(req).await;
+
+	let context = Span::current().context();
+	let span = context.span();
+	let span_context = span.span_context();
+	if !span_context.is_valid() {
+		return response;
+	}
+
+	let traceparent = format!(
+		"00-{}-{}-{:02x}",
+		span_context.trace_id(),
+		span_context.span_id(),
+		span_context.trace_flags()
+	);
+
+	let response_headers = response.headers_mut();
+	if headers.traceresponse {
+		let value = HeaderValue::from_str(&traceparent).expect("Invalid traceresponse");
+		response_headers.insert("traceresponse", value);
+	}
+	if headers.server_timing {
+		let value = format!("traceparent;desc=\"{}\"", traceparent);
+		let value = HeaderValue::from_str(&value).expect("Invalid Server-Timing");
+		response_headers.append("server-timing", value);
+	}
+	if headers.trace_id {
+		let value =
+			HeaderValue::from_str(&span_context.trace_id().to_string()).expect("Invalid trace id");
+		response_headers.insert("x-trace-id", value);
+	}
+
+	response
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::http::server::{collect_from, Options};
+	use crate::testing::{traced_into, Collect};
+	use axum::body::Body;
+	use axum::routing::get;
+	use axum::Router;
+	use futures::executor::block_on;
+	use http::HeaderMap;
+	use opentelemetry::trace::{SpanContext, SpanKind};
+	use tower::ServiceExt;
+
+	// Headers of the response along with the server span
+	fn respond(trace_headers: TraceHeaders) -> (HeaderMap, SpanContext) {
+		let collect = Collect::default();
+		let router = collect_from(
+			Router::new().route("/", get(|| async { "ok" })),
+			Options {
+				trace_headers,
+				..Default::default()
+			},
+		);
+
+		let headers = traced_into(&collect, || {
+			let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+			let response = block_on(router.oneshot(request)).unwrap();
+
+			response.headers().clone()
+		});
+
+		let server = collect
+			.spans()
+			.into_iter()
+			.find(|span| span.span_kind == SpanKind::Server)
+			.expect("the request got a server span");
+
+		(headers, server.span_context)
+	}
+
+	#[test]
+	fn headers_carry_the_server_span() {
+		let (headers, span) = respond(TraceHeaders {
+			traceresponse: true,
+			server_timing: true,
+			trace_id: true,
+		});
+
+		let traceparent = format!("00-{}-{}-01", span.trace_id(), span.span_id());
+		assert_eq!(headers["traceresponse"], traceparent.as_str());
+		assert_eq!(
+			headers["server-timing"],
+			format!("traceparent;desc=\"{}\"", traceparent).as_str()
+		);
+		assert_eq!(headers["x-trace-id"], span.trace_id().to_string().as_str());
+	}
+
+	#[test]
+	fn headers_are_off_by_default() {
+		let (headers, _) = respond(TraceHeaders::default());
+
+		for name in ["traceresponse", "server-timing", "x-trace-id"] {
+			assert!(!headers.contains_key(name), "{} was sent", name);
+		}
+	}
+}
@@ -0,0 +1,26 @@
+use sha2::{Digest, Sha256};
+
+/// Compares secrets in constant time, so that they can't be guessed from response times
+///
+/// Both sides are hashed first, which hides their lengths as well
+pub(super) fn same(left: &[u8], right: &[u8]) -> bool {
+	let (left, right) = (Sha256::digest(left), Sha256::digest(right));
+
+	left.iter()
+		.zip(right.iter())
+		.fold(0, |diff, (left, right)| diff | (left ^ right))
+		== 0
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn matches_equal_secrets_only() {
+		assert!(same(b"s3cret", b"s3cret"));
+		assert!(!same(b"s3cret", b"s3cre"));
+		assert!(!same(b"s3cret", b"s3creT"));
+		assert!(!same(b"", b"s3cret"));
+	}
+}
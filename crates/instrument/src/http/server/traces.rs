@@ -1,6 +1,16 @@
+use super::debug::{DebugTrace, Verdict};
+use super::trust::{Remote, Trust};
+use crate::logs::DebugScope;
+use crate::propagation;
+use crate::traces::Limiter;
+use axum::extract::ConnectInfo;
 use axum::response::Response;
 use http::Request;
-use std::time::Duration;
+use once_cell::sync::Lazy;
+use opentelemetry::trace::{SpanContext, TraceContextExt, TraceFlags};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tower_http::{
 	classify::{ServerErrorsAsFailures, ServerErrorsFailureClass, SharedClassifier},
 	trace::{
@@ -9,8 +19,14 @@ use tower_http::{
 	},
 };
 use tracing::{field::Empty, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
-pub fn layer() -> TraceLayer<
+/// Peers that sent a wrong debug token lately
+static REJECTED: Lazy<Mutex<Limiter>> = Lazy::new(Default::default);
+
+pub fn layer(
+	debug: DebugTrace,
+) -> TraceLayer<
 	SharedClassifier<ServerErrorsAsFailures>,
 	OtelMakeSpan,
 	DefaultOnRequest,
@@ -20,40 +36,113 @@ pub fn layer() -> TraceLayer<
 	OtelOnFailure,
 > {
 	TraceLayer::new_for_http()
-		.make_span_with(OtelMakeSpan)
+		.make_span_with(OtelMakeSpan {
+			debug: Arc::new(debug),
+		})
 		.on_response(OtelOnResponse)
 		.on_failure(OtelOnFailure)
 }
 
-#[derive(Clone, Copy, Debug)]
-pub struct OtelMakeSpan;
+/// Starts request spans on the remote context left in the extensions by [`super::trust::resolve`]
+#[derive(Clone, Debug)]
+pub struct OtelMakeSpan {
+	debug: Arc<DebugTrace>,
+}
 
 impl<B> MakeSpan<B> for OtelMakeSpan {
 	fn make_span(&mut self, req: &Request<B>) -> Span {
 		let http = request::info(req);
 		let name = format!("{} {}", http.method, http.route);
 
-		let span = tracing::info_span!(
-			"HTTP Request",
-
-			otel.name = %name,
-			otel.kind = %"server",
-			otel.status_code = Empty,
-			otel.status_message = Empty,
-
-			http.client_ip = %http.client_ip,
-			http.flavor = %http.flavor,
-			http.host = %http.host,
-			http.method = %http.method,
-			http.route = %http.route,
-			http.scheme = %http.scheme,
-			http.status_code = Empty,
-			http.target = %http.target,
-			http.user_agent = %http.user_agent,
-		);
+		// Clients aren't trusted when nothing resolved their context
+		let Remote {
+			context: remote_context,
+			claimed: link,
+		} = req.extensions()
+			.get::<Remote>()
+			.cloned()
+			.unwrap_or_else(|| Remote::of(&Trust::None, req));
+
+		let verdict = self.debug.check(req);
+		let remote_context = match verdict {
+			Some(Verdict::Granted { .. }) => {
+				let remote = remote_context.span().span_context().clone();
+				let remote_context = if remote.is_valid() {
+					let sampled = SpanContext::new(
+						remote.trace_id(),
+						remote.span_id(),
+						remote.trace_flags() | TraceFlags::SAMPLED,
+						true,
+						remote.trace_state().clone(),
+					);
+
+					remote_context.with_remote_span_context(sampled)
+				} else {
+					remote_context
+				};
+
+				remote_context.with_value(DebugScope)
+			}
+			_ => remote_context,
+		};
 
-		let remote_context = context::create(context::extract(req.headers()));
-		tracing_opentelemetry::OpenTelemetrySpanExt::set_parent(&span, remote_context);
+		// The span is an explicit root, so that a span current in the caller can't take the place
+		// of the remote one
+		let remote_context = propagation::create(remote_context);
+		let span = crate::traces::with_remote_parent(remote_context, || {
+			tracing::info_span!(
+				parent: None,
+				"HTTP Request",
+
+				otel.name = %name,
+				otel.kind = %"server",
+				otel.status_code = Empty,
+				otel.status_message = Empty,
+
+				http.client_ip = %http.client_ip,
+				http.flavor = %http.flavor,
+				http.host = %http.host,
+				http.method = %http.method,
+				http.route = %http.route,
+				http.scheme = %http.scheme,
+				http.status_code = Empty,
+				http.target = %http.target,
+				http.user_agent = %http.user_agent,
+			)
+		});
+
+		if let Some(link) = link {
+			span.add_link(link);
+		}
+
+		let peer = req
+			.extensions()
+			.get::<ConnectInfo<SocketAddr>>()
+			.map(|ConnectInfo(peer)| peer.ip().to_string());
+		match verdict {
+			Some(Verdict::Granted { owner }) => span.in_scope(|| {
+				tracing::warn!(
+					debug.owner = owner,
+					net.peer.ip = peer,
+					"debug trace granted"
+				);
+			}),
+			// Anyone can send a wrong token, so repeats from the same peer are only counted
+			Some(Verdict::Rejected) => {
+				metrics::counter!("debug_trace_rejected_total", 1);
+
+				let suppressed = REJECTED
+					.lock()
+					.unwrap_or_else(|poisoned| poisoned.into_inner())
+					.check(peer.as_deref().unwrap_or_default(), Instant::now());
+				if let Some(suppressed) = suppressed {
+					span.in_scope(|| {
+						tracing::warn!(net.peer.ip = peer, suppressed, "debug trace rejected");
+					});
+				}
+			}
+			None => {}
+		}
 
 		span
 	}
@@ -65,7 +154,7 @@ pub struct OtelOnResponse;
 impl<B> OnResponse<B> for OtelOnResponse {
 	fn on_response(self, response: &Response<B>, _latency: Duration, span: &Span) {
 		let status = response.status().as_u16().to_string();
-		span.record("http.status_code", &tracing::field::display(status));
+		span.record("http.status_code", tracing::field::display(status));
 
 		// assume there is no error, if there is `OtelOnFailure` will be called and override this
 		span.record("otel.status_code", "OK");
@@ -94,50 +183,6 @@ impl OnFailure<ServerErrorsFailureClass> for OtelOnFailure {
 	}
 }
 
-mod context {
-	use opentelemetry::sdk::trace::{IdGenerator, RandomIdGenerator};
-	use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceState};
-
-	struct HeaderExtractor<'a>(&'a http::HeaderMap);
-	impl<'a> opentelemetry::propagation::Extractor for HeaderExtractor<'a> {
-		fn get(&self, key: &str) -> Option<&str> {
-			self.0.get(key).and_then(|value| value.to_str().ok())
-		}
-
-		fn keys(&self) -> Vec<&str> {
-			self.0.keys().map(|value| value.as_str()).collect()
-		}
-	}
-
-	// If remote request has no span data the propagator defaults to an unsampled context
-	pub fn extract(headers: &http::HeaderMap) -> opentelemetry::Context {
-		let extractor = HeaderExtractor(headers);
-
-		opentelemetry::global::get_text_map_propagator(|propagator| propagator.extract(&extractor))
-	}
-
-	// Create a valid sampled context with a trace_id (if not set) before call to
-	// `tracing_opentelemetry::OpenTelemetrySpanExt::set_parent` else trace_id is defined too late
-	// and the `info_span` log `trace_id: ""` Use the default global tracer (named "") to start the
-	// trace
-	pub fn create(remote_context: opentelemetry::Context) -> opentelemetry::Context {
-		if !remote_context.span().span_context().is_valid() {
-			let trace_id = RandomIdGenerator::default().new_trace_id();
-			let span_context = SpanContext::new(
-				trace_id,
-				SpanId::INVALID,
-				TraceFlags::SAMPLED,
-				false,
-				TraceState::default(),
-			);
-
-			remote_context.with_remote_span_context(span_context)
-		} else {
-			remote_context
-		}
-	}
-}
-
 mod request {
 	use axum::extract::{ConnectInfo, MatchedPath, OriginalUri};
 	use http::Request;
@@ -251,3 +296,240 @@ mod request {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::logs::Filter;
+	use crate::testing::{
+		record_metrics, remote_trace_id, traced, traced_into, Collect, Metrics, BAGGAGE,
+		TRACEPARENT,
+	};
+	use opentelemetry::sdk::trace::TracerProvider;
+	use opentelemetry::trace::{TraceId, TracerProvider as _};
+	use opentelemetry::Key;
+	use tracing::field::{Field, Visit};
+	use tracing::Subscriber;
+	use tracing_subscriber::layer::{Context, SubscriberExt};
+	use tracing_subscriber::{EnvFilter, Layer};
+
+	fn debugged() -> OtelMakeSpan {
+		OtelMakeSpan {
+			debug: Arc::new(DebugTrace {
+				tokens: vec![(String::from("alice"), String::from("s3cret"))],
+				..Default::default()
+			}),
+		}
+	}
+
+	fn debug_request(traceparent: &str, token: Option<&str>) -> Request<()> {
+		let mut req = Request::builder().header("traceparent", traceparent);
+		if let Some(token) = token {
+			req = req.header("x-debug-trace", token);
+		}
+		resolved(&Trust::All, req.body(()).unwrap())
+	}
+
+	// `req` with its remote context resolved, as the trust middleware does
+	fn resolved(trust: &Trust, mut req: Request<()>) -> Request<()> {
+		crate::testing::propagate();
+		let remote = Remote::of(trust, &req);
+		req.extensions_mut().insert(remote);
+
+		req
+	}
+
+	// Messages of the events that got through the filters
+	#[derive(Clone, Default)]
+	struct Messages(Arc<Mutex<Vec<String>>>);
+
+	impl<S: Subscriber> Layer<S> for Messages {
+		fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+			struct Message(Option<String>);
+
+			impl Visit for Message {
+				fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+					if field.name() == "message" {
+						self.0 = Some(format!("{:?}", value));
+					}
+				}
+			}
+
+			let mut message = Message(None);
+			event.record(&mut message);
+			self.0.lock().unwrap().extend(message.0);
+		}
+	}
+
+	// Trace of the request span, the traces it links to and the baggage it carries
+	fn request_trace(trust: &Trust, req: Request<()>) -> (TraceId, Vec<TraceId>, Option<String>) {
+		let mut make_span = OtelMakeSpan {
+			debug: Arc::new(DebugTrace::default()),
+		};
+		let req = resolved(trust, req);
+
+		let collect = Collect::default();
+		traced_into(&collect, || drop(make_span.make_span(&req)));
+
+		let spans = collect.spans();
+		let [span] = spans.as_slice() else {
+			panic!("expected the request span alone, got {:?}", spans);
+		};
+		let links = span
+			.links
+			.iter()
+			.map(|link| link.span_context.trace_id())
+			.collect();
+		let baggage = span
+			.attributes
+			.get(&Key::new("baggage.tenant.id"))
+			.map(|value| value.to_string());
+
+		(span.span_context.trace_id(), links, baggage)
+	}
+
+	#[test]
+	fn continues_the_remote_trace_under_a_current_span() {
+		let mut make_span = OtelMakeSpan {
+			debug: Arc::new(DebugTrace::default()),
+		};
+		let req = Request::builder()
+			.header("traceparent", TRACEPARENT)
+			.body(())
+			.unwrap();
+		let req = resolved(&Trust::All, req);
+
+		let trace_id = traced(|| {
+			let _outer = tracing::info_span!("outer").entered();
+			let span = make_span.make_span(&req);
+
+			span.context().span().span_context().trace_id()
+		});
+
+		assert_eq!(trace_id, remote_trace_id());
+	}
+
+	#[test]
+	fn untrusted_requests_start_a_trace_linked_to_the_claimed_one() {
+		let header: Trust = "header:x-edge-secret=s3cret".parse().unwrap();
+		let req = |secret: Option<&str>| {
+			let mut req = Request::builder()
+				.header("traceparent", TRACEPARENT)
+				.header("baggage", "tenant.id=acme");
+			if let Some(secret) = secret {
+				req = req.header("x-edge-secret", secret);
+			}
+			req.body(()).unwrap()
+		};
+
+		for (trust, req) in [
+			(Trust::None, req(Some("s3cret"))),
+			(header.clone(), req(None)),
+			(header.clone(), req(Some("wrong"))),
+		] {
+			let (trace_id, links, baggage) = request_trace(&trust, req);
+			assert_ne!(trace_id, remote_trace_id(), "{:?}", trust);
+			assert_eq!(links, [remote_trace_id()], "{:?}", trust);
+			assert_eq!(baggage, None, "{:?} drops the baggage", trust);
+		}
+
+		let (trace_id, links, baggage) = request_trace(&header, req(Some("s3cret")));
+		assert_eq!(trace_id, remote_trace_id());
+		assert!(links.is_empty());
+		assert_eq!(baggage.as_deref(), Some("acme"));
+
+		let (_, links, _) = request_trace(&Trust::None, Request::new(()));
+		assert!(links.is_empty(), "requests claiming no trace link to none");
+	}
+
+	#[test]
+	fn raises_debug_only_within_granted_requests() {
+		crate::testing::propagate();
+		crate::logs::enable_debug();
+
+		let messages = Messages::default();
+		let provider = TracerProvider::builder().build();
+		let subscriber = tracing_subscriber::registry()
+			.with(Filter::new(EnvFilter::new("info")))
+			.with(crate::traces::layer(provider.tracer("test"), BAGGAGE, None))
+			.with(messages.clone());
+
+		let mut make_span = debugged();
+		tracing::subscriber::with_default(subscriber, || {
+			let granted = make_span.make_span(&debug_request(TRACEPARENT, Some("s3cret")));
+			granted.in_scope(|| {
+				tracing::debug!("granted");
+				tracing::info_span!("child").in_scope(|| tracing::debug!("nested"));
+			});
+
+			let plain = make_span.make_span(&debug_request(TRACEPARENT, None));
+			plain.in_scope(|| tracing::debug!("plain"));
+			tracing::debug!("outside");
+		});
+
+		assert_eq!(
+			*messages.0.lock().unwrap(),
+			["debug trace granted", "granted", "nested"]
+		);
+	}
+
+	#[test]
+	fn logs_rejected_tokens_once_per_peer_and_window() {
+		let messages = Messages::default();
+		let subscriber = tracing_subscriber::registry()
+			.with(Filter::new(EnvFilter::new("warn")))
+			.with(messages.clone());
+
+		let rejected = |peer: &str| {
+			let mut req = debug_request(TRACEPARENT, Some("wrong"));
+			let peer: SocketAddr = peer.parse().unwrap();
+			req.extensions_mut().insert(ConnectInfo(peer));
+			req
+		};
+
+		let mut make_span = debugged();
+		tracing::subscriber::with_default(subscriber, || {
+			make_span.make_span(&rejected("203.0.113.7:80"));
+			make_span.make_span(&rejected("203.0.113.7:81"));
+			make_span.make_span(&rejected("203.0.113.8:80"));
+		});
+
+		assert_eq!(
+			*messages.0.lock().unwrap(),
+			["debug trace rejected", "debug trace rejected"]
+		);
+	}
+
+	#[test]
+	fn granted_requests_get_sampled() {
+		record_metrics();
+		let unsampled = TRACEPARENT.replace("-01", "-00");
+		let mut make_span = debugged();
+
+		let mut sampled = |req: &Request<()>| {
+			traced(|| {
+				let span = make_span.make_span(req);
+				let context = span.context().span().span_context().clone();
+
+				(context.trace_id(), context.is_sampled())
+			})
+		};
+
+		assert_eq!(
+			sampled(&debug_request(&unsampled, Some("s3cret"))),
+			(remote_trace_id(), true)
+		);
+		assert_eq!(
+			sampled(&debug_request(&unsampled, None)),
+			(remote_trace_id(), false)
+		);
+		assert_eq!(
+			sampled(&debug_request(&unsampled, Some("wrong"))),
+			(remote_trace_id(), false)
+		);
+		assert_eq!(
+			Metrics::snapshot().counter("debug_trace_rejected_total", &[]),
+			1
+		);
+	}
+}
@@ -0,0 +1,230 @@
+use super::secret;
+use crate::propagation::{self, HeaderExtractor};
+use axum::extract::ConnectInfo;
+use axum::middleware::Next;
+use axum::response::Response;
+use http::Request;
+use opentelemetry::baggage::BaggageExt;
+use opentelemetry::trace::{SpanContext, TraceContextExt};
+use opentelemetry::Context;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Clients allowed to pick the trace of their requests through the propagation headers
+///
+/// Requests from anyone else start a new trace, linked to the one they claimed to be part of, and
+/// lose their baggage. Networks are matched against the peer address, which axum only provides
+/// when serving with `into_make_service_with_connect_info::<SocketAddr>`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Trust {
+	/// For services only reachable by other services
+	#[default]
+	All,
+	None,
+	/// Requests with `name` set to `value`, e.g. a secret added by the edge proxy
+	Header {
+		name: String,
+		value: String,
+	},
+	/// Requests coming from any of the networks
+	Networks(Vec<Network>),
+}
+
+impl FromStr for Trust {
+	type Err = String;
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value.trim() {
+			"all" => Ok(Trust::All),
+			"none" => Ok(Trust::None),
+			other => {
+				if let Some(header) = other.strip_prefix("header:") {
+					let (name, value) = header
+						.split_once('=')
+						.ok_or_else(|| format!("header trust should be name=value: {}", header))?;
+
+					Ok(Trust::Header {
+						name: name.trim().to_lowercase(),
+						value: value.trim().to_string(),
+					})
+				} else if let Some(networks) = other.strip_prefix("networks:") {
+					let networks = networks
+						.split(',')
+						.map(str::parse)
+						.collect::<Result<_, _>>()?;
+
+					Ok(Trust::Networks(networks))
+				} else {
+					Err(format!("unknown trust policy: {}", other))
+				}
+			}
+		}
+	}
+}
+
+impl Trust {
+	pub fn trusts<B>(&self, req: &Request<B>) -> bool {
+		match self {
+			Trust::All => true,
+			Trust::None => false,
+			Trust::Header { name, value } => req
+				.headers()
+				.get(name.as_str())
+				.is_some_and(|header| secret::same(header.as_bytes(), value.as_bytes())),
+			Trust::Networks(networks) => req
+				.extensions()
+				.get::<ConnectInfo<SocketAddr>>()
+				.is_some_and(|ConnectInfo(peer)| {
+					networks.iter().any(|network| network.contains(peer.ip()))
+				}),
+		}
+	}
+}
+
+/// Context a request carries in, as far as its client is trusted
+///
+/// Kept in the request extensions, so that the request span and the handlers see the same one
+#[derive(Clone, Debug)]
+pub(super) struct Remote {
+	pub context: Context,
+	/// Valid trace an untrusted client claimed to be part of
+	pub claimed: Option<SpanContext>,
+}
+
+impl Remote {
+	/// Untrusted clients keep neither their baggage nor their trace, which only gets linked
+	pub fn of<B>(trust: &Trust, req: &Request<B>) -> Self {
+		let context = propagation::extract(&HeaderExtractor(req.headers()));
+		if trust.trusts(req) {
+			return Remote {
+				context,
+				claimed: None,
+			};
+		}
+
+		let claimed = context.span().span_context().clone();
+		Remote {
+			context: context
+				.with_cleared_baggage()
+				.with_remote_span_context(SpanContext::empty_context()),
+			claimed: Some(claimed).filter(SpanContext::is_valid),
+		}
+	}
+}
+
+/// Resolves the remote context of the request, must come before anything reading it
+pub(super) async fn resolve<B>(trust: Arc<Trust>, mut req: Request<B>, next: Next<B>) -> Response {
+	let remote = Remote::of(&trust, &req);
+	req.extensions_mut().insert(remote);
+
+	next.run(req).await
+}
+
+/// Range of addresses in CIDR notation, a bare address is a network of its own
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Network {
+	address: IpAddr,
+	prefix: u8,
+}
+
+impl FromStr for Network {
+	type Err = String;
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		let invalid = || format!("invalid network: {}", value);
+
+		let (address, prefix) = match value.trim().split_once('/') {
+			Some((address, prefix)) => (address, Some(prefix)),
+			None => (value.trim(), None),
+		};
+
+		let address: IpAddr = address.parse().map_err(|_| invalid())?;
+		let bits = if address.is_ipv4() { 32 } else { 128 };
+		let prefix = match prefix {
+			Some(prefix) => prefix.parse().map_err(|_| invalid())?,
+			None => bits,
+		};
+
+		if prefix > bits {
+			return Err(invalid());
+		}
+
+		Ok(Network { address, prefix })
+	}
+}
+
+impl Network {
+	/// Whether `ip` is within the network, IPv4-mapped IPv6 addresses as the IPv4 ones they map
+	pub fn contains(&self, ip: IpAddr) -> bool {
+		// Dual-stack listeners see IPv4 peers as ::ffff:a.b.c.d
+		let ip = match (self.address, ip) {
+			(IpAddr::V4(_), IpAddr::V6(v6)) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+			_ => ip,
+		};
+
+		let (network, ip, bits) = match (self.address, ip) {
+			(IpAddr::V4(network), IpAddr::V4(ip)) => {
+				(u32::from(network) as u128, u32::from(ip) as u128, 32)
+			}
+			(IpAddr::V6(network), IpAddr::V6(ip)) => (u128::from(network), u128::from(ip), 128),
+			_ => return false,
+		};
+
+		let shift = bits - u32::from(self.prefix);
+		let mask = u128::MAX.checked_shl(shift).unwrap_or(0) & (u128::MAX >> (128 - bits));
+
+		network & mask == ip & mask
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn matches_peers_within_networks() {
+		let trust: Trust = "networks:10.0.0.0/8, ::1".parse().unwrap();
+
+		let request = |peer: &str| {
+			let mut req = Request::new(());
+			let peer: SocketAddr = peer.parse().unwrap();
+			req.extensions_mut().insert(ConnectInfo(peer));
+			req
+		};
+
+		assert!(trust.trusts(&request("10.1.2.3:80")));
+		assert!(trust.trusts(&request("[::1]:80")));
+		assert!(
+			trust.trusts(&request("[::ffff:10.1.2.3]:80")),
+			"IPv4-mapped peers match IPv4 networks"
+		);
+		assert!(!trust.trusts(&request("[::ffff:11.1.2.3]:80")));
+		assert!(!trust.trusts(&request("11.1.2.3:80")));
+		assert!(
+			!trust.trusts(&Request::new(())),
+			"peers are unknown without connect info"
+		);
+		assert!("0.0.0.0/0"
+			.parse::<Network>()
+			.unwrap()
+			.contains([1, 2, 3, 4].into()));
+		assert!("10.0.0.0/33".parse::<Network>().is_err());
+	}
+
+	#[test]
+	fn matches_the_header_value() {
+		let trust: Trust = "header:X-Edge-Secret=s3cret".parse().unwrap();
+
+		let request = |value: &str| {
+			Request::builder()
+				.header("x-edge-secret", value)
+				.body(())
+				.unwrap()
+		};
+
+		assert!(trust.trusts(&request("s3cret")));
+		assert!(!trust.trusts(&request("s3cre")));
+		assert!(!trust.trusts(&Request::new(())));
+	}
+}
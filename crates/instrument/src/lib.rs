@@ -1,16 +1,29 @@
 #![feature(panic_info_message, thread_id_value)]
 
+pub mod context;
 pub mod http;
 mod logs;
 mod metrics;
+pub mod propagation;
+pub mod task;
+#[cfg(test)]
+mod testing;
 mod traces;
 
+pub use logs::{Format as LogFormat, Keys as LogKeys, Limits as LogLimits, Severity, Timestamp};
+pub use traces::otlp_json;
+pub use traces::{
+	Batch as TraceBatch, Buffer as TraceBuffer, Exporter as TraceExporter, IdGenerator,
+	Limits as TraceLimits, Propagator, Retention as TraceRetention, Transport,
+};
+
 use std::panic;
 use tracing::{error, Span};
-use tracing_core::Subscriber;
+use tracing_core::{LevelFilter, Subscriber};
+use tracing_log::{AsLog, LogTracer};
+use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::EnvFilter;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 pub trait Sub: Subscriber + for<'span> LookupSpan<'span> {}
 impl<T: Subscriber + for<'span> LookupSpan<'span>> Sub for T {}
@@ -24,7 +37,54 @@ pub struct Options<'a> {
 	pub level: &'a str,
 	pub service: &'a str,
 	pub version: &'a str,
-	pub exporter: &'a str,
+	/// Destinations of spans, all of them get every span
+	pub exporters: &'a [TraceExporter<'a>],
+	/// Formats of trace context read from requests and sent on outgoing ones, as in `OTEL_PROPAGATORS`
+	pub propagators: &'a [Propagator],
+	/// Bounds on the attributes, events and links of spans
+	pub trace_limits: TraceLimits,
+	/// Source of ids for spans and for the traces started by incoming requests, a later `init` with
+	/// another one panics
+	pub id_generator: IdGenerator,
+	/// Holds unsampled traces for a while and exports those with a failed span, off when `None`
+	pub trace_retention: Option<TraceRetention>,
+	/// Baggage keys copied onto server spans and the `context` of their log lines
+	pub baggage: &'a [&'a str],
+	/// Span fields used as labels of span duration and error metrics, which are off when `None`
+	pub span_metrics: Option<&'a [&'a str]>,
+	/// Histograms exposed as buckets carrying trace exemplars, the others stay summaries. All of
+	/// them when `None`
+	pub histogram_buckets: Option<&'a [&'a str]>,
+	/// Ratio of `info` and more verbose log lines kept for traces that weren't sampled
+	pub log_sampling: f64,
+	/// Encoding of timestamps and severities in log lines
+	pub log_format: LogFormat,
+	/// Size bounds of log lines and their fields
+	pub log_limits: LogLimits,
+	/// Fields added to the `context` of every log line, e.g. `("deployment.environment", "prod")`
+	pub log_fields: &'a [(&'a str, &'a str)],
+}
+
+impl Default for Options<'_> {
+	fn default() -> Self {
+		Options {
+			level: "info",
+			service: "",
+			version: "",
+			exporters: &[TraceExporter::LOCAL],
+			propagators: &[Propagator::TraceContext],
+			trace_limits: TraceLimits::default(),
+			id_generator: IdGenerator::default(),
+			trace_retention: None,
+			baggage: &[],
+			span_metrics: None,
+			histogram_buckets: None,
+			log_sampling: 1.0,
+			log_format: LogFormat::default(),
+			log_limits: LogLimits::default(),
+			log_fields: &[],
+		}
+	}
 }
 
 pub fn init(opts: Options) -> Instrument {
@@ -32,21 +92,57 @@ pub fn init(opts: Options) -> Instrument {
 		level,
 		service,
 		version,
-		exporter,
+		exporters,
+		propagators,
+		trace_limits,
+		id_generator,
+		trace_retention,
+		baggage,
+		span_metrics,
+		histogram_buckets,
+		log_sampling,
+		log_format,
+		log_limits,
+		log_fields,
 	} = opts;
 
-	tracing_subscriber::registry()
-		.with(EnvFilter::try_new(level).unwrap())
-		.with(traces::init(traces::Options {
-			service,
-			version,
-			exporter,
-		}))
-		.with(logs::init())
-		.try_init()
+	let tracer = traces::init(traces::Options {
+		service,
+		version,
+		exporters,
+		propagators,
+		limits: &trace_limits,
+		id_generator,
+		retention: trace_retention.as_ref(),
+	});
+
+	let subscriber = tracing_subscriber::registry()
+		.with(logs::Filter::new(EnvFilter::try_new(level).unwrap()))
+		.with(traces::layer(
+			tracer.clone(),
+			baggage,
+			trace_limits.attribute_length,
+		))
+		.with(traces::metrics(span_metrics))
+		.with(logs::init(logs::Options {
+			sampling: log_sampling,
+			format: log_format,
+			limits: log_limits,
+			fields: log_fields,
+			tracer,
+		}));
+
+	tracing::subscriber::set_global_default(subscriber)
 		.expect("Unable to register tracing subscriber");
 
-	metrics::init();
+	// Records from the `log` crate go through the same `EnvFilter` as native events, the max level
+	// only spares disabled ones from being bridged at all
+	LogTracer::builder()
+		.with_max_level(LevelFilter::current().as_log())
+		.init()
+		.expect("Unable to register log bridge");
+
+	metrics::init(histogram_buckets);
 
 	panic::set_hook(Box::new(|info| {
 		let message = match info.message() {
@@ -0,0 +1,103 @@
+use crate::Sub;
+use opentelemetry::Context as OtelContext;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Level, Metadata};
+use tracing_core::callsite;
+use tracing_core::subscriber::Interest;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::{EnvFilter, Layer};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Value of the OTel context under which spans get their `debug` lines logged, along with the
+/// lines of every span under them
+#[derive(Clone, Copy, Debug)]
+pub struct DebugScope;
+
+/// Allows spans to raise their level, which keeps `debug` callsites from being disabled for good
+pub fn enable() {
+	if !ENABLED.swap(true, Ordering::AcqRel) {
+		callsite::rebuild_interest_cache();
+	}
+}
+
+/// `EnvFilter` letting `debug` spans and events through within a [`DebugScope`]
+pub struct Filter {
+	env: EnvFilter,
+}
+
+impl Filter {
+	pub fn new(env: EnvFilter) -> Self {
+		Filter { env }
+	}
+}
+
+fn raisable(metadata: &Metadata<'_>) -> bool {
+	ENABLED.load(Ordering::Acquire) && *metadata.level() <= Level::DEBUG
+}
+
+impl<S: Sub> Layer<S> for Filter {
+	fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+		let interest = Layer::<S>::register_callsite(&self.env, metadata);
+
+		if interest.is_never() && raisable(metadata) {
+			Interest::sometimes()
+		} else {
+			interest
+		}
+	}
+
+	fn max_level_hint(&self) -> Option<LevelFilter> {
+		let hint = self.env.max_level_hint();
+
+		if ENABLED.load(Ordering::Acquire) {
+			hint.map(|hint| hint.max(LevelFilter::DEBUG))
+		} else {
+			hint
+		}
+	}
+
+	fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+		if self.env.enabled(metadata, ctx.clone()) {
+			return true;
+		}
+
+		if !raisable(metadata) {
+			return false;
+		}
+
+		match ctx.lookup_current() {
+			Some(span) => span
+				.scope()
+				.any(|span| span.extensions().get::<DebugScope>().is_some()),
+			None => false,
+		}
+	}
+
+	fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+		self.env.on_new_span(attrs, id, ctx.clone());
+
+		if ENABLED.load(Ordering::Acquire) && OtelContext::current().get::<DebugScope>().is_some() {
+			let span = ctx.span(id).expect("Span not found, this is a bug");
+			span.extensions_mut().insert(DebugScope);
+		}
+	}
+
+	fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+		self.env.on_record(id, values, ctx)
+	}
+
+	fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+		self.env.on_enter(id, ctx)
+	}
+
+	fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+		self.env.on_exit(id, ctx)
+	}
+
+	fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+		self.env.on_close(id, ctx)
+	}
+}
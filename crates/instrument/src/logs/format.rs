@@ -0,0 +1,145 @@
+use super::store::Store;
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use serde_json::{json, Value};
+use tracing::Level;
+
+/// Encoding of the values generated for each log line
+#[derive(Clone, Debug, Default)]
+pub struct Format {
+	pub keys: Keys,
+	pub timestamp: Timestamp,
+	pub severity: Severity,
+	/// Add `uptime_ms`, the monotonic time since initialization, to order lines within a process
+	pub uptime: bool,
+}
+
+/// Names of the keys at the top level of log lines
+#[derive(Clone, Debug)]
+pub struct Keys {
+	pub schema: String,
+	pub message: String,
+	pub level: String,
+	pub timestamp: String,
+	pub uptime: String,
+	pub otel: String,
+	pub context: String,
+	pub data: String,
+	pub runtime: String,
+}
+
+impl Default for Keys {
+	fn default() -> Self {
+		Keys {
+			schema: "schema".to_string(),
+			message: "message".to_string(),
+			level: "level".to_string(),
+			timestamp: "timestamp".to_string(),
+			uptime: "uptime_ms".to_string(),
+			otel: "otel".to_string(),
+			context: "context".to_string(),
+			data: "data".to_string(),
+			runtime: "runtime".to_string(),
+		}
+	}
+}
+
+impl Keys {
+	/// Moves the default keys of `root` to their configured names
+	pub fn rename(&self, root: &mut Store) {
+		let names = [
+			("schema", &self.schema),
+			("message", &self.message),
+			("level", &self.level),
+			("timestamp", &self.timestamp),
+			("uptime_ms", &self.uptime),
+			("otel", &self.otel),
+			("context", &self.context),
+			("data", &self.data),
+			("runtime", &self.runtime),
+		];
+
+		let mut renamed = Store::new();
+		for (key, name) in names {
+			if let Some(value) = root.remove(key) {
+				renamed.insert(name.to_string(), value);
+			}
+		}
+
+		root.port_all(&mut renamed);
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Timestamp {
+	/// RFC 3339 string, e.g. `2023-01-31T12:00:00.000Z`
+	Rfc3339 {
+		/// Use nanoseconds instead of milliseconds
+		nanos: bool,
+		/// Use the local timezone offset instead of UTC
+		local: bool,
+	},
+	/// Milliseconds since the Unix epoch
+	UnixMillis,
+}
+
+impl Default for Timestamp {
+	fn default() -> Self {
+		Timestamp::Rfc3339 {
+			nanos: false,
+			local: false,
+		}
+	}
+}
+
+impl Timestamp {
+	pub fn encode(&self, now: &DateTime<Utc>) -> Value {
+		match *self {
+			Timestamp::Rfc3339 { nanos, local } => {
+				let precision = if nanos {
+					SecondsFormat::Nanos
+				} else {
+					SecondsFormat::Millis
+				};
+
+				if local {
+					json!(now.with_timezone(&Local).to_rfc3339_opts(precision, true))
+				} else {
+					json!(now.to_rfc3339_opts(precision, true))
+				}
+			}
+			Timestamp::UnixMillis => json!(now.timestamp_millis()),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Severity {
+	/// Lowercase level name, e.g. `info`
+	#[default]
+	Name,
+	/// OpenTelemetry `SeverityNumber`
+	Otel,
+	/// Syslog severity from RFC 5424
+	Syslog,
+}
+
+impl Severity {
+	pub fn encode(&self, level: &Level) -> Value {
+		match self {
+			Severity::Name => json!(level.to_string().to_lowercase()),
+			Severity::Otel => json!(match *level {
+				Level::TRACE => 1,
+				Level::DEBUG => 5,
+				Level::INFO => 9,
+				Level::WARN => 13,
+				Level::ERROR => 17,
+			}),
+			Severity::Syslog => json!(match *level {
+				Level::TRACE | Level::DEBUG => 7,
+				Level::INFO => 6,
+				Level::WARN => 4,
+				Level::ERROR => 3,
+			}),
+		}
+	}
+}
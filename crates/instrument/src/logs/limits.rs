@@ -0,0 +1,68 @@
+use super::store::Store;
+use serde_json::{json, Value};
+
+/// Bounds on the size of log lines, values beyond them are cut and marked as truncated
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+	/// Max bytes of a string value
+	pub field_length: Option<usize>,
+	/// Max fields in `context` and `data`
+	pub fields: Option<usize>,
+	/// Max nesting of a field value, deeper values are serialized into a string
+	pub depth: Option<usize>,
+	/// Max bytes of a serialized line, never exceeded
+	pub line: Option<usize>,
+}
+
+impl Limits {
+	/// Serializes `root` within the line limit, dropping `sections` and then shortening the
+	/// message until it fits
+	pub fn fit(&self, mut root: Store, sections: &[&str], message: &str) -> (String, usize) {
+		let mut output = json!(root).to_string();
+		let mut truncations = 0;
+
+		let max = match self.line {
+			Some(max) => max,
+			None => return (output, truncations),
+		};
+
+		for section in sections {
+			if output.len() <= max {
+				return (output, truncations);
+			}
+
+			if let Some(value) = root.get_mut(*section) {
+				let size = value.to_string().len();
+				*value = json!(format!("...(truncated {} bytes)", size));
+				truncations += 1;
+
+				output = json!(root).to_string();
+			}
+		}
+
+		if output.len() > max {
+			if let Some(Value::String(value)) = root.get_mut(message) {
+				// Escapes only make the serialized message longer than its bytes, so cutting the
+				// excess off the bytes shortens the line at least as much
+				let excess = output.len() - max;
+				let length = value.len().saturating_sub(excess);
+				truncations += super::store::truncate(value, length);
+
+				output = json!(root).to_string();
+			}
+		}
+
+		// Everything else was too much on its own, e.g. long span names with a short message
+		if output.len() > max {
+			let marker = json!({ message: format!("...(truncated {} bytes)", output.len()) });
+			truncations += 1;
+
+			output = marker.to_string();
+			if output.len() > max {
+				output = json!({}).to_string();
+			}
+		}
+
+		(output, truncations)
+	}
+}
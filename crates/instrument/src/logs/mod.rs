@@ -1,24 +1,74 @@
+mod debug;
+mod format;
+mod limits;
+mod sampling;
 mod store;
 
-use self::store::{PortBy, Store};
+pub(crate) use self::debug::{enable as enable_debug, DebugScope, Filter};
+pub use self::format::{Format, Keys, Severity, Timestamp};
+pub use self::limits::Limits;
+use self::sampling::Sampling;
+use self::store::PortBy;
+pub(crate) use self::store::{truncate, Store};
 use super::Sub;
+use crate::traces::BaggageFields;
 use chrono::DateTime;
-use chrono::{SecondsFormat, Utc};
-use opentelemetry::trace::TraceContextExt;
+use chrono::Utc;
+use once_cell::sync::OnceCell;
+use opentelemetry::sdk::trace::Tracer;
+use opentelemetry::trace::{SpanId, TraceContextExt, TraceFlags, TraceId};
 use serde_json::{json, Value};
+use std::io::Write;
 use std::thread::ThreadId;
-use tracing::{field::FieldSet, span::Record, Event, Metadata, Span};
+use std::time::{Duration, Instant};
+use tracing::{Event, Metadata};
 
-use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_log::NormalizeEvent;
+use tracing_opentelemetry::{OtelData, PreSampledTracer};
+use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::registry::Scope;
 use tracing_subscriber::Layer;
 
-pub fn init<S: Sub>() -> impl Layer<S> {
-	LogLayer
+/// Version of the layout of log lines, to be bumped whenever sections or fields move
+const SCHEMA: &str = "instrument.log/v1";
+
+static START: OnceCell<Instant> = OnceCell::new();
+
+pub struct Options<'a> {
+	pub sampling: f64,
+	pub format: Format,
+	pub limits: Limits,
+	pub fields: &'a [(&'a str, &'a str)],
+	pub tracer: Tracer,
+}
+
+pub fn init<S: Sub>(opts: Options) -> impl Layer<S> {
+	START.get_or_init(Instant::now);
+
+	let mut fields = Store::new();
+	for (key, value) in opts.fields {
+		fields.insert(key.to_string(), json!(value));
+	}
+
+	LogLayer {
+		format: opts.format,
+		limits: opts.limits,
+		fields,
+		tracer: opts.tracer,
+		writer: std::io::stdout,
+	}
+	.with_filter(Sampling::new(opts.sampling))
+}
+
+struct LogLayer<W> {
+	format: Format,
+	limits: Limits,
+	fields: Store,
+	tracer: Tracer,
+	writer: W,
 }
 
-struct LogLayer;
-impl<S: Sub> Layer<S> for LogLayer {
+impl<S: Sub, W: for<'w> MakeWriter<'w> + 'static> Layer<S> for LogLayer<W> {
 	fn on_new_span(
 		&self,
 		attrs: &tracing::span::Attributes<'_>,
@@ -31,7 +81,23 @@ impl<S: Sub> Layer<S> for LogLayer {
 		attrs.record(&mut store);
 
 		let mut extensions = span.extensions_mut();
+		if let Some(BaggageFields(fields)) = extensions.get_mut::<BaggageFields>() {
+			for (key, value) in fields {
+				store.insert(key.clone(), json!(value));
+			}
+		}
+
 		extensions.insert(store);
+
+		// The span was just built by the OpenTelemetry layer, so resolving its context here fixes the
+		// sampling decision and lets every event get the ids, even if the span is never entered
+		let correlation = extensions
+			.get_mut::<OtelData>()
+			.and_then(|data| Correlation::new(&self.tracer, data));
+
+		if let Some(correlation) = correlation {
+			extensions.insert(correlation);
+		}
 	}
 
 	fn on_record(
@@ -49,73 +115,90 @@ impl<S: Sub> Layer<S> for LogLayer {
 	}
 
 	fn on_event(&self, event: &tracing::Event<'_>, ctx: tracing_subscriber::layer::Context<'_, S>) {
+		let Limits {
+			field_length,
+			fields: field_count,
+			depth,
+			..
+		} = self.limits;
+		let mut truncations = 0;
+
 		let fields: Store = {
-			let context: Store = ctx.event_scope(event).map(Scope::into).unwrap_or_default();
-			let mut metadata: Store = event.metadata().into();
+			let mut context: Store = {
+				let mut spans: Store = ctx.event_scope(event).map(Scope::into).unwrap_or_default();
+
+				let mut context = self.fields.clone();
+				context.port_all(&mut crate::context::current());
+				context.port_all(&mut spans);
+
+				context
+			};
+			let correlation: Store = ctx
+				.event_span(event)
+				.and_then(|span| span.extensions().get::<Correlation>().map(Store::from))
+				.unwrap_or_default();
+			// Records bridged from `log` come from a single callsite per level, so their real
+			// location is only available in the normalized metadata
+			let normalized = event.normalized_metadata();
+			let event_metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+			let event_level = event_metadata.level();
+
+			let mut metadata: Store = event_metadata.into();
 			let mut event: Store = event.into();
-			let mut live: Store = (&Live::new()).into();
+			let mut live: Store = Live::new().fields(&self.format);
+
+			if normalized.is_some() {
+				event.retain(|key, _| !key.starts_with("log."));
+			}
+
+			// The message is only shortened to fit the line, as the last resort
+			let mut message = Store::new();
+			message.port(&mut event, vec!["message"]);
+
+			truncations += context.truncate_values(field_length, depth);
+			truncations += event.truncate_values(field_length, depth);
 
 			let mut runtime = Store::new();
 			runtime.port(&mut live, vec!["thread"]);
 			runtime
 				.port_by(&mut event, by_prefix("panic.", vec!["line", "file"]))
-				.or_else(|runtime| {
-					runtime.port_by(
-						&mut event,
-						by_prefix("log.", vec!["target", "line", "file"]),
-					)
-				})
 				.or_else(|runtime| runtime.port(&mut metadata, vec!["target", "line", "file"]));
 
 			let mut root = Store::new();
-			root.port(&mut event, vec!["message"]);
-			root.port(&mut metadata, vec!["level"]);
-			root.port(&mut live, vec!["timestamp"]);
+			root.insert("schema".to_string(), json!(SCHEMA));
+			root.port_all(&mut message);
+			root.insert(
+				"level".to_string(),
+				self.format.severity.encode(event_level),
+			);
+			root.port(&mut live, vec!["timestamp", "uptime_ms"]);
+
+			if let Some(count) = field_count {
+				truncations += context.truncate_fields(count);
+				truncations += event.truncate_fields(count);
+			}
 
+			root.push("otel", correlation);
 			root.push("context", context);
 			root.push("data", event);
 			root.push("runtime", runtime);
 
+			self.format.keys.rename(&mut root);
+
 			root
 		};
 
-		let output = json!(fields);
-
-		println!("{}", output);
-	}
+		let keys = &self.format.keys;
+		let (output, line_truncations) =
+			self.limits
+				.fit(fields, &[&keys.data, &keys.context], &keys.message);
+		truncations += line_truncations;
 
-	fn on_enter(
-		&self,
-		id: &tracing_core::span::Id,
-		ctx: tracing_subscriber::layer::Context<'_, S>,
-	) {
-		let tracing_context = Span::current().context();
-		let otel_span = tracing_context.span();
-		let otel_ctx = otel_span.span_context();
-
-		if otel_ctx.is_valid() {
-			const TRACE_PROP: &str = "otel.trace_id";
-			let trace_id = otel_ctx.trace_id().to_string();
-			const SPAN_PROP: &str = "otel.span_id";
-			let span_id = otel_ctx.span_id().to_string();
-
-			let field_set = FieldSet::new(
-				&[TRACE_PROP, SPAN_PROP],
-				ctx.metadata(id).unwrap().callsite(),
-			);
-
-			let trace_field = field_set.field(TRACE_PROP).unwrap();
-			let span_field = field_set.field(SPAN_PROP).unwrap();
-
-			let values = [
-				(&trace_field, Some(&trace_id as &dyn tracing::Value)),
-				(&span_field, Some(&span_id as &dyn tracing::Value)),
-			];
-			let values = field_set.value_set(&values);
-			let record = Record::new(&values);
-
-			self.on_record(id, &record, ctx);
+		if truncations > 0 {
+			metrics::counter!("log_truncations_total", truncations as u64);
 		}
+
+		let _ = writeln!(self.writer.make_writer(), "{}", output);
 	}
 }
 
@@ -135,9 +218,35 @@ fn by_prefix<'a>(prefix: &'a str, allowed: Vec<&'a str>) -> PortBy<'a> {
 	})
 }
 
+/// Trace ids of a span, resolved once when the span is created
+struct Correlation {
+	trace_id: TraceId,
+	span_id: SpanId,
+	flags: TraceFlags,
+}
+
+impl Correlation {
+	fn new(tracer: &Tracer, data: &mut OtelData) -> Option<Correlation> {
+		let context = tracer.sampled_context(data);
+		let span = context.span();
+		let span_context = span.span_context();
+
+		if !span_context.is_valid() {
+			return None;
+		}
+
+		Some(Correlation {
+			trace_id: span_context.trace_id(),
+			span_id: span_context.span_id(),
+			flags: span_context.trace_flags(),
+		})
+	}
+}
+
 struct Live {
 	thread: ThreadId,
 	now: DateTime<Utc>,
+	uptime: Duration,
 }
 
 impl Live {
@@ -145,7 +254,27 @@ impl Live {
 		Live {
 			thread: std::thread::current().id(),
 			now: Utc::now(),
+			uptime: START.get().map(Instant::elapsed).unwrap_or_default(),
+		}
+	}
+
+	fn fields(&self, format: &Format) -> Store {
+		let mut fields = Store::new();
+
+		let mut data: Vec<(&str, Value)> = vec![
+			("thread", json!(self.thread.as_u64())),
+			("timestamp", format.timestamp.encode(&self.now)),
+		];
+
+		if format.uptime {
+			data.push(("uptime_ms", json!(self.uptime.as_millis() as u64)));
+		}
+
+		for (key, value) in data {
+			fields.insert(key.to_string(), value);
 		}
+
+		fields
 	}
 }
 
@@ -169,7 +298,6 @@ impl From<&Metadata<'_>> for Store {
 
 		let data: Vec<(&str, Value)> = vec![
 			("target", json!(value.target())),
-			("level", json!(value.level().to_string().to_lowercase())),
 			("line", json!(value.line())),
 			("file", json!(value.file())),
 		];
@@ -191,16 +319,15 @@ impl From<&Event<'_>> for Store {
 	}
 }
 
-impl From<&Live> for Store {
-	fn from(value: &Live) -> Self {
+impl From<&Correlation> for Store {
+	fn from(value: &Correlation) -> Self {
 		let mut fields = Store::new();
 
 		let data: Vec<(&str, Value)> = vec![
-			("thread", json!(value.thread.as_u64())),
-			(
-				"timestamp",
-				json!(value.now.to_rfc3339_opts(SecondsFormat::Millis, true)),
-			),
+			("trace_id", json!(value.trace_id.to_string())),
+			("span_id", json!(value.span_id.to_string())),
+			("trace_flags", json!(format!("{:02x}", value.flags))),
+			("trace_sampled", json!(value.flags.is_sampled())),
 		];
 
 		for (key, value) in data {
@@ -210,3 +337,385 @@ impl From<&Live> for Store {
 		fields
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use opentelemetry::sdk::trace::{config, Sampler, TracerProvider};
+	use opentelemetry::trace::TracerProvider as _;
+	use std::sync::{Arc, Mutex};
+	use tracing_log::LogTracer;
+	use tracing_subscriber::layer::SubscriberExt;
+
+	#[derive(Clone, Default)]
+	struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+	impl Write for Buffer {
+		fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+			self.0.lock().unwrap().write(buf)
+		}
+
+		fn flush(&mut self) -> std::io::Result<()> {
+			Ok(())
+		}
+	}
+
+	impl<'a> MakeWriter<'a> for Buffer {
+		type Writer = Buffer;
+
+		fn make_writer(&'a self) -> Self::Writer {
+			self.clone()
+		}
+	}
+
+	fn capture(f: impl FnOnce()) -> Vec<Value> {
+		capture_with(Format::default(), Limits::default(), f)
+	}
+
+	fn capture_with(format: Format, limits: Limits, f: impl FnOnce()) -> Vec<Value> {
+		capture_sampled(format, limits, Sampler::AlwaysOn, 1.0, f)
+	}
+
+	fn capture_sampled(
+		format: Format,
+		limits: Limits,
+		sampler: Sampler,
+		sampling: f64,
+		f: impl FnOnce(),
+	) -> Vec<Value> {
+		let buffer = Buffer::default();
+		let provider = TracerProvider::builder()
+			.with_config(config().with_sampler(sampler))
+			.build();
+		let tracer = provider.tracer("test");
+		let layer = LogLayer {
+			format,
+			limits,
+			fields: Store::new(),
+			tracer: tracer.clone(),
+			writer: buffer.clone(),
+		};
+
+		let subscriber = tracing_subscriber::registry()
+			.with(crate::traces::layer(tracer, crate::testing::BAGGAGE, None))
+			.with(layer.with_filter(Sampling::new(sampling)));
+		tracing::subscriber::with_default(subscriber, f);
+
+		let output = buffer.0.lock().unwrap();
+		String::from_utf8_lossy(&output)
+			.lines()
+			.map(|line| serde_json::from_str(line).unwrap())
+			.collect()
+	}
+
+	#[test]
+	fn envelope_keys_can_be_renamed() {
+		let format = Format {
+			keys: Keys {
+				message: "msg".to_string(),
+				timestamp: "@timestamp".to_string(),
+				data: "fields".to_string(),
+				..Default::default()
+			},
+			..Default::default()
+		};
+
+		let lines = capture_with(format, Limits::default(), || {
+			tracing::info!(answer = 42, "hi")
+		});
+		let line = &lines[0];
+
+		assert_eq!(line["schema"], SCHEMA);
+		assert_eq!(line["msg"], "hi");
+		assert!(line["@timestamp"].is_string());
+		assert_eq!(line["fields"], json!({ "answer": 42 }));
+		assert!(line.get("message").is_none());
+		assert!(line.get("data").is_none());
+	}
+
+	#[test]
+	fn native_events_report_their_callsite() {
+		let lines = capture(|| tracing::info!(answer = 42, "native"));
+		let line = &lines[0];
+
+		assert_eq!(line["message"], "native");
+		assert_eq!(line["level"], "info");
+		assert_eq!(line["data"], json!({ "answer": 42 }));
+		assert_eq!(line["runtime"]["target"], module_path!());
+		assert_eq!(line["runtime"]["file"], file!());
+		assert!(line["runtime"]["line"].is_u64());
+		assert!(line["runtime"]["thread"].is_u64());
+	}
+
+	#[test]
+	fn bridged_records_report_their_callsite() {
+		let _ = LogTracer::init();
+
+		let lines = capture(|| log::warn!(target: "bridged", "from log"));
+		let line = &lines[0];
+
+		assert_eq!(line["message"], "from log");
+		assert_eq!(line["level"], "warn");
+		assert!(line.get("data").is_none());
+		assert_eq!(line["runtime"]["target"], "bridged");
+		assert_eq!(line["runtime"]["file"], file!());
+		assert!(line["runtime"]["line"].is_u64());
+		assert!(line["runtime"]["thread"].is_u64());
+	}
+
+	#[test]
+	fn context_fields_survive_await_points() {
+		let lines = capture(|| {
+			let runtime = tokio::runtime::Builder::new_current_thread()
+				.build()
+				.unwrap();
+
+			let future = crate::context::with_fields([("tenant_id", "acme")], async {
+				tokio::task::yield_now().await;
+				tracing::info!("after await");
+			});
+
+			runtime.block_on(future);
+		});
+
+		assert_eq!(lines[0]["context"]["tenant_id"], "acme");
+	}
+
+	#[test]
+	fn allowed_baggage_lands_in_the_context() {
+		crate::testing::propagate();
+		let carrier = std::collections::HashMap::from([
+			(
+				"traceparent".to_string(),
+				crate::testing::TRACEPARENT.to_string(),
+			),
+			(
+				"baggage".to_string(),
+				"tenant.id=acme,region=eu".to_string(),
+			),
+		]);
+
+		let lines = capture(|| {
+			let span = crate::propagation::consumer_span("process", &carrier);
+			let _entered = span.enter();
+			tracing::info!("inside");
+		});
+
+		assert_eq!(lines[0]["context"]["baggage.tenant.id"], "acme");
+		assert!(lines[0]["context"].get("baggage.region").is_none());
+	}
+
+	#[test]
+	fn oversized_fields_are_truncated() {
+		let limits = Limits {
+			field_length: Some(32),
+			fields: Some(3),
+			..Default::default()
+		};
+
+		let lines = capture_with(Format::default(), limits, || {
+			tracing::info!(
+				a = "x".repeat(40),
+				b = "0123456789",
+				c = 1,
+				d = 2,
+				"{}",
+				"m".repeat(40)
+			);
+		});
+
+		assert_eq!(lines[0]["data"]["a"], "xxxxxxxxx...(truncated 31 bytes)");
+		assert_eq!(
+			lines[0]["data"]["b"], "0123456789",
+			"values within the limit are kept"
+		);
+		assert_eq!(lines[0]["data"]["..."], "...(truncated 2 fields)");
+		assert!(lines[0]["data"].get("c").is_none());
+		assert_eq!(
+			lines[0]["message"],
+			"m".repeat(40),
+			"the message is only cut to fit the line"
+		);
+	}
+
+	#[test]
+	fn oversized_lines_are_truncated() {
+		let limits = Limits {
+			line: Some(300),
+			..Default::default()
+		};
+
+		let lines = capture_with(Format::default(), limits, || {
+			tracing::info!(body = "x".repeat(400), "{}", "y".repeat(600));
+		});
+
+		assert!(lines[0]["data"]
+			.as_str()
+			.unwrap()
+			.starts_with("...(truncated"));
+		assert!(lines[0]["message"]
+			.as_str()
+			.unwrap()
+			.contains("...(truncated"));
+		assert!(lines[0].to_string().len() <= 300);
+	}
+
+	#[test]
+	fn limits_are_hard_caps() {
+		for field_length in [0, 5, 23, 24, 40] {
+			let mut value = "é".repeat(30);
+			store::truncate(&mut value, field_length);
+			assert!(value.len() <= field_length, "{}", value);
+		}
+
+		for line in [40, 250, 300, 1000] {
+			let limits = Limits {
+				line: Some(line),
+				..Default::default()
+			};
+			let output = capture_with(Format::default(), limits, || {
+				let span = tracing::info_span!("span", name = %"x".repeat(line));
+				let _entered = span.enter();
+				tracing::info!(body = "x".repeat(400), "{}", "\"é\n".repeat(300));
+			});
+
+			assert!(output[0].to_string().len() <= line, "{}", output[0]);
+		}
+
+		for fields in [0, 1, 2, 5] {
+			let limits = Limits {
+				fields: Some(fields),
+				..Default::default()
+			};
+			let output = capture_with(Format::default(), limits, || {
+				tracing::info!(a = 1, b = 2, c = 3, d = 4, "message");
+			});
+
+			let data = output[0]["data"].as_object().map_or(0, |data| data.len());
+			assert!(data <= fields, "{}", output[0]);
+		}
+	}
+
+	#[test]
+	fn unsampled_traces_keep_a_share_of_lines() {
+		let traces = || {
+			for _ in 0..32 {
+				let span = tracing::info_span!("trace");
+				let _guard = span.enter();
+				tracing::info!("info");
+				tracing::warn!("warn");
+			}
+			tracing::info!("outside");
+		};
+		// Trace ids of the `warn` lines, which are always kept, and whether the `info` one was too
+		let kept = |lines: Vec<Value>| {
+			let infos: Vec<_> = lines
+				.iter()
+				.filter(|line| line["message"] == "info")
+				.map(|line| line["otel"]["trace_id"].clone())
+				.collect();
+			let outside = lines.iter().filter(|line| line["message"] == "outside");
+			assert_eq!(outside.count(), 1, "lines outside traces are kept");
+
+			lines
+				.iter()
+				.filter(|line| line["message"] == "warn")
+				.map(|line| {
+					let trace_id = &line["otel"]["trace_id"];
+					let id = TraceId::from_hex(trace_id.as_str().unwrap()).unwrap();
+					(
+						id,
+						line["otel"]["trace_sampled"] == true,
+						infos.contains(trace_id),
+					)
+				})
+				.collect::<Vec<_>>()
+		};
+
+		let lines = capture_sampled(
+			Format::default(),
+			Limits::default(),
+			Sampler::AlwaysOff,
+			0.5,
+			traces,
+		);
+		let sampling = Sampling::new(0.5);
+		let traces_kept = kept(lines);
+		assert_eq!(traces_kept.len(), 32);
+		for &(trace_id, _, info) in &traces_kept {
+			assert_eq!(info, sampling.keep(trace_id), "kept by trace id");
+		}
+		assert!(traces_kept.iter().any(|&(_, _, info)| info));
+		assert!(traces_kept.iter().any(|&(_, _, info)| !info));
+
+		let lines = capture_sampled(
+			Format::default(),
+			Limits::default(),
+			Sampler::TraceIdRatioBased(0.5),
+			0.0,
+			traces,
+		);
+		for (_, sampled, info) in kept(lines) {
+			assert_eq!(info, sampled, "lines of sampled traces are kept");
+		}
+	}
+
+	fn ids(span: &tracing::Span) -> Value {
+		use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+		let context = span.context();
+		let span = context.span();
+		let span_context = span.span_context();
+
+		json!([
+			span_context.trace_id().to_string(),
+			span_context.span_id().to_string()
+		])
+	}
+
+	fn logged_ids(line: &Value) -> Value {
+		json!([line["otel"]["trace_id"], line["otel"]["span_id"]])
+	}
+
+	#[test]
+	fn never_entered_spans_are_correlated() {
+		let mut expected = Value::Null;
+		let lines = capture(|| {
+			let span = tracing::info_span!("idle");
+			tracing::info!(parent: &span, "inside");
+			expected = ids(&span);
+		});
+
+		assert_eq!(logged_ids(&lines[0]), expected);
+		assert_eq!(lines[0]["otel"]["trace_sampled"], true);
+	}
+
+	#[test]
+	fn spans_with_an_explicit_parent_share_its_trace() {
+		let mut expected = (Value::Null, Value::Null);
+		let lines = capture(|| {
+			let parent = tracing::info_span!("parent");
+			let _other = tracing::info_span!("other").entered();
+			let child = tracing::info_span!(parent: &parent, "child");
+			tracing::info!(parent: &child, "inside");
+			expected = (ids(&parent), ids(&child));
+		});
+
+		let (parent, child) = expected;
+		assert_eq!(logged_ids(&lines[0]), child);
+		assert_eq!(lines[0]["otel"]["trace_id"], parent[0]);
+	}
+
+	#[test]
+	fn events_before_the_first_enter_are_correlated() {
+		let lines = capture(|| {
+			let span = tracing::info_span!("late");
+			tracing::info!(parent: &span, "before");
+			let _entered = span.enter();
+			tracing::info!("after");
+		});
+
+		assert!(lines[0]["otel"]["span_id"].is_string());
+		assert_eq!(logged_ids(&lines[0]), logged_ids(&lines[1]));
+	}
+}
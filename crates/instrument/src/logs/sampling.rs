@@ -0,0 +1,54 @@
+use super::Correlation;
+use crate::Sub;
+use opentelemetry::trace::TraceId;
+use tracing::{Event, Level, Metadata};
+use tracing_subscriber::layer::{Context, Filter};
+
+/// Filter that keeps only a share of the `info` and more verbose lines of unsampled traces
+///
+/// Lines from sampled traces, lines outside of traces and `warn`/`error` lines are always kept.
+/// The decision is derived from the trace id, the same way as `TraceIdRatioBased`, so all lines
+/// of a trace are either kept or dropped together
+pub struct Sampling {
+	bound: u64,
+}
+
+impl Sampling {
+	pub fn new(ratio: f64) -> Self {
+		let ratio = ratio.clamp(0.0, 1.0);
+
+		Sampling {
+			bound: (ratio * (1u64 << 63) as f64) as u64,
+		}
+	}
+
+	pub(super) fn keep(&self, trace_id: TraceId) -> bool {
+		let bytes = trace_id.to_bytes();
+		let random = u64::from_be_bytes(bytes[8..16].try_into().unwrap()) >> 1;
+
+		random < self.bound
+	}
+}
+
+impl<S: Sub> Filter<S> for Sampling {
+	fn enabled(&self, _meta: &Metadata<'_>, _cx: &Context<'_, S>) -> bool {
+		true
+	}
+
+	fn event_enabled(&self, event: &Event<'_>, cx: &Context<'_, S>) -> bool {
+		if *event.metadata().level() <= Level::WARN {
+			return true;
+		}
+
+		let span = match cx.event_span(event) {
+			Some(span) => span,
+			None => return true,
+		};
+
+		let extensions = span.extensions();
+		match extensions.get::<Correlation>() {
+			Some(correlation) => correlation.flags.is_sampled() || self.keep(correlation.trace_id),
+			None => true,
+		}
+	}
+}
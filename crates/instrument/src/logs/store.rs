@@ -73,6 +73,35 @@ impl Store {
 		self.0.append(&mut from.0);
 	}
 
+	/// Shortens strings longer than `length` and serializes values nested deeper than `depth`,
+	/// returning how many values were truncated
+	pub fn truncate_values(&mut self, length: Option<usize>, depth: Option<usize>) -> usize {
+		self.values_mut()
+			.map(|value| truncate_value(value, length, depth))
+			.sum()
+	}
+
+	/// Keeps the first fields and a marker of how many were left out, `count` keys in all
+	pub fn truncate_fields(&mut self, count: usize) -> usize {
+		if self.len() <= count {
+			return 0;
+		}
+
+		let kept = count.saturating_sub(1);
+		let dropped = self.len() - kept;
+		let first_dropped = self.keys().nth(kept).cloned().unwrap();
+		self.split_off(&first_dropped);
+
+		if count > 0 {
+			self.insert(
+				"...".to_string(),
+				json!(format!("...(truncated {} fields)", dropped)),
+			);
+		}
+
+		1
+	}
+
 	pub fn push(&mut self, field: &str, from: Self) {
 		if from.is_empty() {
 			return;
@@ -82,6 +111,58 @@ impl Store {
 	}
 }
 
+fn truncate_value(value: &mut Value, length: Option<usize>, depth: Option<usize>) -> usize {
+	match value {
+		Value::String(value) => length.map_or(0, |length| truncate(value, length)),
+		Value::Array(_) | Value::Object(_) if depth == Some(0) => {
+			let mut serialized = value.to_string();
+			if let Some(length) = length {
+				truncate(&mut serialized, length);
+			}
+			*value = Value::String(serialized);
+
+			1
+		}
+		Value::Array(values) => values
+			.iter_mut()
+			.map(|value| truncate_value(value, length, depth.map(|depth| depth - 1)))
+			.sum(),
+		Value::Object(values) => values
+			.values_mut()
+			.map(|value| truncate_value(value, length, depth.map(|depth| depth - 1)))
+			.sum(),
+		_ => 0,
+	}
+}
+
+/// Cuts `value` on a char boundary so that it ends up within `length` bytes, marker included
+///
+/// The marker is left out when `length` can't even hold it
+pub fn truncate(value: &mut String, length: usize) -> usize {
+	if value.len() <= length {
+		return 0;
+	}
+
+	// The count in the marker has at most as many digits as the whole length
+	let room = marker(value.len()).len();
+	let mut end = length.checked_sub(room).unwrap_or(length);
+	while !value.is_char_boundary(end) {
+		end -= 1;
+	}
+
+	let cut = value.len() - end;
+	value.truncate(end);
+	if room <= length {
+		value.push_str(&marker(cut));
+	}
+
+	1
+}
+
+fn marker(bytes: usize) -> String {
+	format!("...(truncated {} bytes)", bytes)
+}
+
 impl Serialize for Store {
 	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
 	where
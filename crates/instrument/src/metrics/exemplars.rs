@@ -0,0 +1,475 @@
+use metrics::{Counter, Gauge, Histogram, HistogramFn, Key, KeyName, Recorder, SharedString, Unit};
+use opentelemetry::trace::{TraceContextExt, TraceId};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Name and sorted labels identifying a histogram series
+type Series = (String, Vec<(String, String)>);
+
+const SHARDS: usize = 16;
+
+struct Exemplar {
+	trace_id: TraceId,
+	value: f64,
+	timestamp: f64,
+}
+
+/// Latest exemplar of each bucket of every histogram series recorded so far
+///
+/// Series are sharded by key, so that looking one up as the `histogram!` macros do on each call
+/// rarely waits on another being added
+#[derive(Default)]
+pub struct Exemplars {
+	shards: [RwLock<HashMap<Key, Arc<Slots>>>; SHARDS],
+}
+
+impl Exemplars {
+	fn slots(&self, key: &Key, buckets: &'static [f64]) -> Arc<Slots> {
+		let shard = &self.shards[key.get_hash() as usize % SHARDS];
+
+		if let Some(slots) = shard.read().unwrap().get(key) {
+			return slots.clone();
+		}
+
+		shard
+			.write()
+			.unwrap()
+			.entry(key.clone())
+			.or_insert_with(|| {
+				Arc::new(Slots {
+					buckets,
+					slots: (0..=buckets.len()).map(|_| Mutex::new(None)).collect(),
+				})
+			})
+			.clone()
+	}
+
+	fn series(&self) -> HashMap<Series, Arc<Slots>> {
+		let mut series = HashMap::new();
+
+		for shard in &self.shards {
+			for (key, slots) in shard.read().unwrap().iter() {
+				let mut labels: Vec<(String, String)> = key
+					.labels()
+					.map(|label| (label.key().to_string(), label.value().to_string()))
+					.collect();
+				labels.sort();
+
+				series.insert((key.name().to_string(), labels), slots.clone());
+			}
+		}
+
+		series
+	}
+}
+
+/// Exemplars of a series, one per bucket and the last one standing for `+Inf`
+struct Slots {
+	buckets: &'static [f64],
+	slots: Vec<Mutex<Option<Exemplar>>>,
+}
+
+impl Slots {
+	fn record(&self, exemplar: Exemplar) {
+		let bucket = self
+			.buckets
+			.iter()
+			.position(|bound| exemplar.value <= *bound)
+			.unwrap_or(self.buckets.len());
+
+		// Samples racing for the same bucket are as good an exemplar as each other, so the one
+		// that finds it taken just moves on
+		if let Ok(mut slot) = self.slots[bucket].try_lock() {
+			*slot = Some(exemplar);
+		}
+	}
+}
+
+/// Keeps the trace of the latest sample in each bucket of the `bucketed` histograms, or of every
+/// one when `None`, when recorded within a sampled span
+pub struct ExemplarRecorder<R> {
+	inner: R,
+	bucketed: Option<Vec<String>>,
+	buckets: &'static [f64],
+	exemplars: Arc<Exemplars>,
+}
+
+impl<R> ExemplarRecorder<R> {
+	pub fn new(
+		inner: R,
+		bucketed: Option<Vec<String>>,
+		buckets: &'static [f64],
+		exemplars: Arc<Exemplars>,
+	) -> Self {
+		ExemplarRecorder {
+			inner,
+			bucketed,
+			buckets,
+			exemplars,
+		}
+	}
+}
+
+impl<R: Recorder> Recorder for ExemplarRecorder<R> {
+	fn describe_counter(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+		self.inner.describe_counter(key, unit, description)
+	}
+
+	fn describe_gauge(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+		self.inner.describe_gauge(key, unit, description)
+	}
+
+	fn describe_histogram(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+		self.inner.describe_histogram(key, unit, description)
+	}
+
+	fn register_counter(&self, key: &Key) -> Counter {
+		self.inner.register_counter(key)
+	}
+
+	fn register_gauge(&self, key: &Key) -> Gauge {
+		self.inner.register_gauge(key)
+	}
+
+	fn register_histogram(&self, key: &Key) -> Histogram {
+		let inner = self.inner.register_histogram(key);
+		let bucketed = match &self.bucketed {
+			Some(names) => names.iter().any(|name| name == key.name()),
+			None => true,
+		};
+		if !bucketed {
+			return inner;
+		}
+
+		Histogram::from_arc(Arc::new(ExemplarHistogram {
+			inner,
+			slots: self.exemplars.slots(key, self.buckets),
+		}))
+	}
+}
+
+struct ExemplarHistogram {
+	inner: Histogram,
+	slots: Arc<Slots>,
+}
+
+impl HistogramFn for ExemplarHistogram {
+	fn record(&self, value: f64) {
+		self.inner.record(value);
+
+		let context = Span::current().context();
+		let span = context.span();
+		let span_context = span.span_context();
+		if !span_context.is_sampled() {
+			return;
+		}
+
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_secs_f64();
+
+		self.slots.record(Exemplar {
+			trace_id: span_context.trace_id(),
+			value,
+			timestamp,
+		});
+	}
+}
+
+/// Converts the Prometheus text output into OpenMetrics, adding exemplars to histogram buckets
+pub fn openmetrics(text: &str, exemplars: &Exemplars) -> String {
+	let series = exemplars.series();
+	let mut output = String::with_capacity(text.len());
+
+	// OpenMetrics names counter families without the `_total` of their samples, and the HELP line
+	// comes before the TYPE one telling that it's a counter. Counters whose samples lack the suffix
+	// go out as `unknown` rather than renamed, so a series is called the same in both formats
+	let counters: HashSet<&str> = text
+		.lines()
+		.filter_map(|line| line.strip_prefix("# TYPE ")?.strip_suffix("_total counter"))
+		.collect();
+
+	for line in text.lines() {
+		if line.is_empty() {
+			continue;
+		}
+
+		if let Some(family) = line.strip_prefix("# TYPE ") {
+			if let Some(name) = family.strip_suffix("_total counter") {
+				let _ = writeln!(output, "# TYPE {} counter", name);
+				continue;
+			}
+			if let Some(name) = family.strip_suffix(" counter") {
+				let _ = writeln!(output, "# TYPE {} unknown", name);
+				continue;
+			}
+		}
+
+		if let Some(family) = line.strip_prefix("# HELP ") {
+			let (name, help) = family.split_at(family.find(' ').unwrap_or(family.len()));
+			let counter = name
+				.strip_suffix("_total")
+				.filter(|name| counters.contains(name));
+			if let Some(name) = counter {
+				let _ = writeln!(output, "# HELP {}{}", name, help);
+				continue;
+			}
+		}
+
+		output.push_str(line);
+		if let Some(slot) = exemplar(line, &series) {
+			if let Some(exemplar) = slot.lock().unwrap().as_ref() {
+				let _ = write!(
+					output,
+					" # {{trace_id=\"{}\"}} {} {:.3}",
+					exemplar.trace_id, exemplar.value, exemplar.timestamp
+				);
+			}
+		}
+		output.push('\n');
+	}
+
+	output.push_str("# EOF\n");
+
+	output
+}
+
+fn exemplar<'a>(
+	line: &str,
+	series: &'a HashMap<Series, Arc<Slots>>,
+) -> Option<&'a Mutex<Option<Exemplar>>> {
+	let (name, rest) = line.split_once("_bucket{")?;
+	let (labels, _) = rest.rsplit_once('}')?;
+
+	let (le, mut labels): (Vec<_>, Vec<_>) = parse_labels(labels)
+		.into_iter()
+		.partition(|(key, _)| key == "le");
+	labels.sort();
+
+	let slots = series.get(&(name.to_string(), labels))?;
+	let bucket = match le.first()?.1.as_str() {
+		"+Inf" => slots.buckets.len(),
+		le => {
+			let bound: f64 = le.parse().ok()?;
+			slots.buckets.iter().position(|known| *known == bound)?
+		}
+	};
+
+	slots.slots.get(bucket)
+}
+
+// Parses `key="value",...` undoing the escaping of `\`, `"` and newlines in values
+fn parse_labels(labels: &str) -> Vec<(String, String)> {
+	let mut parsed = Vec::new();
+	let mut rest = labels;
+
+	while let Some((key, tail)) = rest.split_once("=\"") {
+		let mut value = String::new();
+		let mut chars = tail.char_indices();
+		let mut end = tail.len();
+
+		while let Some((index, char)) = chars.next() {
+			match char {
+				'"' => {
+					end = index + 1;
+					break;
+				}
+				'\\' => match chars.next() {
+					Some((_, 'n')) => value.push('\n'),
+					Some((_, escaped)) => value.push(escaped),
+					None => {}
+				},
+				char => value.push(char),
+			}
+		}
+
+		parsed.push((key.trim_start_matches(',').to_string(), value));
+		rest = &tail[end..];
+	}
+
+	parsed
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::{remote_trace_id, traced, TRACEPARENT};
+	use metrics::NoopRecorder;
+	use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+	use openmetrics_parser::openmetrics::parse_openmetrics;
+	use openmetrics_parser::{OpenMetricsType, OpenMetricsValue};
+	use std::collections::HashMap as Carrier;
+
+	const BUCKETS: &[f64] = &[0.1, 0.5];
+
+	fn recorder(exemplars: &Arc<Exemplars>) -> ExemplarRecorder<NoopRecorder> {
+		ExemplarRecorder::new(
+			NoopRecorder,
+			Some(vec![String::from("test_seconds")]),
+			BUCKETS,
+			exemplars.clone(),
+		)
+	}
+
+	#[test]
+	fn buckets_carry_their_exemplar() {
+		let exemplars = Arc::new(Exemplars::default());
+		let key = Key::from_parts("test_seconds", vec![metrics::Label::new("path", "/a\"b")]);
+		exemplars.slots(&key, BUCKETS).record(Exemplar {
+			trace_id: remote_trace_id(),
+			value: 0.3,
+			timestamp: 1.5,
+		});
+
+		let text = "# TYPE test_seconds histogram\n\
+			test_seconds_bucket{path=\"/a\\\"b\",le=\"0.1\"} 0\n\
+			test_seconds_bucket{path=\"/a\\\"b\",le=\"0.5\"} 1\n\
+			test_seconds_bucket{path=\"/a\\\"b\",le=\"+Inf\"} 1\n\
+			\n\
+			# HELP test_total Tests run.\n\
+			# TYPE test_total counter\n\
+			test_total 1\n\
+			# TYPE tests counter\n\
+			tests 1\n";
+
+		assert_eq!(
+			openmetrics(text, &exemplars),
+			"# TYPE test_seconds histogram\n\
+			test_seconds_bucket{path=\"/a\\\"b\",le=\"0.1\"} 0\n\
+			test_seconds_bucket{path=\"/a\\\"b\",le=\"0.5\"} 1 # {trace_id=\"4bf92f3577b34da6a3ce929d0e0e4736\"} 0.3 1.500\n\
+			test_seconds_bucket{path=\"/a\\\"b\",le=\"+Inf\"} 1\n\
+			# HELP test Tests run.\n\
+			# TYPE test counter\n\
+			test_total 1\n\
+			# TYPE tests unknown\n\
+			tests 1\n\
+			# EOF\n"
+		);
+	}
+
+	#[test]
+	fn records_the_trace_of_bucketed_histograms_in_sampled_spans() {
+		let exemplars = Arc::new(Exemplars::default());
+		let recorder = recorder(&exemplars);
+		let carrier = Carrier::from([("traceparent".to_string(), TRACEPARENT.to_string())]);
+
+		traced(|| {
+			let key = Key::from_name("test_seconds");
+			recorder.register_histogram(&key).record(0.05);
+
+			let span = crate::propagation::consumer_span("process", &carrier);
+			let _entered = span.enter();
+			recorder.register_histogram(&key).record(2.0);
+			recorder
+				.register_histogram(&Key::from_name("other_seconds"))
+				.record(2.0);
+		});
+
+		let slots = &exemplars.series()[&("test_seconds".to_string(), Vec::new())];
+		assert!(
+			slots.slots[0].lock().unwrap().is_none(),
+			"samples outside of sampled spans have no trace"
+		);
+		let inf = slots.slots[2].lock().unwrap();
+		let exemplar = inf.as_ref().unwrap();
+		assert_eq!(exemplar.trace_id, remote_trace_id());
+		assert_eq!(exemplar.value, 2.0);
+		assert_eq!(
+			exemplars.series().len(),
+			1,
+			"other histograms keep summaries"
+		);
+	}
+
+	#[test]
+	fn renders_what_openmetrics_parsers_accept() {
+		let inner = PrometheusBuilder::new()
+			.set_buckets_for_metric(Matcher::Full("test_seconds".to_string()), BUCKETS)
+			.unwrap()
+			.build_recorder();
+		let handle = inner.handle();
+		let exemplars = Arc::new(Exemplars::default());
+		let recorder = ExemplarRecorder::new(
+			inner,
+			Some(vec![String::from("test_seconds")]),
+			BUCKETS,
+			exemplars.clone(),
+		);
+		let carrier = Carrier::from([("traceparent".to_string(), TRACEPARENT.to_string())]);
+
+		traced(|| {
+			let span = crate::propagation::consumer_span("process", &carrier);
+			let _entered = span.enter();
+			let labels = vec![metrics::Label::new("path", "/")];
+			recorder
+				.register_histogram(&Key::from_parts("test_seconds", labels))
+				.record(0.3);
+			recorder
+				.register_histogram(&Key::from_name("other_seconds"))
+				.record(0.3);
+		});
+		recorder.describe_counter("test_total".into(), None, "Tests run.".into());
+		recorder
+			.register_counter(&Key::from_name("test_total"))
+			.increment(1);
+		recorder
+			.register_counter(&Key::from_name("tests"))
+			.increment(1);
+		recorder
+			.register_gauge(&Key::from_name("test_bytes"))
+			.set(1.0);
+
+		let output = openmetrics(&handle.render(), &exemplars);
+		let parsed = parse_openmetrics(&output).unwrap_or_else(|err| panic!("{err:?}\n{output}"));
+
+		let types: HashMap<&str, OpenMetricsType> = parsed
+			.families
+			.iter()
+			.map(|(name, family)| (name.as_str(), family.family_type))
+			.collect();
+		assert_eq!(
+			types,
+			HashMap::from([
+				("test_seconds", OpenMetricsType::Histogram),
+				("other_seconds", OpenMetricsType::Summary),
+				("test", OpenMetricsType::Counter),
+				("tests", OpenMetricsType::Unknown),
+				("test_bytes", OpenMetricsType::Gauge),
+			])
+		);
+
+		let sample = parsed.families["test_seconds"]
+			.iter_samples()
+			.next()
+			.unwrap();
+		let OpenMetricsValue::Histogram(histogram) = &sample.value else {
+			panic!("{:?}", sample.value);
+		};
+		let exemplar = histogram.buckets[1].exemplar.as_ref().unwrap();
+		assert_eq!(exemplar.labels["trace_id"], remote_trace_id().to_string());
+	}
+
+	#[test]
+	fn every_histogram_is_bucketed_unless_named() {
+		let exemplars = Arc::new(Exemplars::default());
+		let recorder = ExemplarRecorder::new(NoopRecorder, None, BUCKETS, exemplars.clone());
+		let carrier = Carrier::from([("traceparent".to_string(), TRACEPARENT.to_string())]);
+
+		traced(|| {
+			let span = crate::propagation::consumer_span("process", &carrier);
+			let _entered = span.enter();
+			for name in ["test_seconds", "other_seconds"] {
+				recorder
+					.register_histogram(&Key::from_name(name))
+					.record(0.3);
+			}
+		});
+
+		assert_eq!(exemplars.series().len(), 2);
+	}
+}
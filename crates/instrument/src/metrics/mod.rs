@@ -0,0 +1,64 @@
+mod exemplars;
+
+use self::exemplars::{ExemplarRecorder, Exemplars};
+use axum_prometheus::metrics_exporter_prometheus::PrometheusHandle;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+use once_cell::sync::OnceCell;
+use std::sync::Arc;
+
+static HANDLE: OnceCell<(PrometheusHandle, Arc<Exemplars>)> = OnceCell::new();
+
+/// Buckets of the histograms, same as the Prometheus client defaults
+const BUCKETS: &[f64] = &[
+	0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Installs the recorder, with the `bucketed` histograms exposed as buckets carrying exemplars
+/// and every other one as a summary, or all of them bucketed when `None`
+pub fn init(bucketed: Option<&[&str]>) {
+	HANDLE.get_or_init(|| {
+		let mut builder = PrometheusBuilder::new();
+		match bucketed {
+			Some(names) => {
+				for name in names {
+					builder = builder
+						.set_buckets_for_metric(Matcher::Full(name.to_string()), BUCKETS)
+						.expect("Unable to set histogram buckets");
+				}
+			}
+			None => {
+				builder = builder
+					.set_buckets(BUCKETS)
+					.expect("Unable to set histogram buckets");
+			}
+		}
+
+		let recorder = builder.build_recorder();
+		let handle = recorder.handle();
+		let exemplars = Arc::new(Exemplars::default());
+
+		let bucketed = bucketed.map(|names| names.iter().map(|name| name.to_string()).collect());
+		metrics::set_boxed_recorder(Box::new(ExemplarRecorder::new(
+			recorder,
+			bucketed,
+			BUCKETS,
+			exemplars.clone(),
+		)))
+		.expect("Unable to install prometheus recorder");
+
+		(handle, exemplars)
+	});
+}
+
+/// Renders the metrics in the Prometheus text format, or as OpenMetrics with exemplars
+pub fn render(openmetrics: bool) -> String {
+	let (handle, exemplars) = HANDLE
+		.get()
+		.expect("Should have initialized metrics module first");
+
+	if openmetrics {
+		exemplars::openmetrics(&handle.render(), exemplars)
+	} else {
+		handle.render()
+	}
+}
@@ -0,0 +1,187 @@
+use http::header::{HeaderName, HeaderValue};
+use http::HeaderMap;
+use opentelemetry::global;
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceState};
+use opentelemetry::Context;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+pub use opentelemetry::propagation::{Extractor, Injector};
+
+/// Carrier writing into HTTP headers
+pub struct HeaderInjector<'a>(pub &'a mut HeaderMap);
+
+impl<'a> Injector for HeaderInjector<'a> {
+	fn set(&mut self, key: &str, value: String) {
+		let name = HeaderName::from_bytes(key.as_bytes());
+		let value = HeaderValue::from_str(&value);
+
+		if let (Ok(name), Ok(value)) = (name, value) {
+			self.0.insert(name, value);
+		}
+	}
+}
+
+/// Carrier reading from HTTP headers
+pub struct HeaderExtractor<'a>(pub &'a HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+	fn get(&self, key: &str) -> Option<&str> {
+		self.0.get(key).and_then(|value| value.to_str().ok())
+	}
+
+	fn keys(&self) -> Vec<&str> {
+		self.0.keys().map(|value| value.as_str()).collect()
+	}
+}
+
+/// Carrier writing into binary headers, as used by most message brokers
+pub struct BytesInjector<'a>(pub &'a mut Vec<(String, Vec<u8>)>);
+
+impl<'a> Injector for BytesInjector<'a> {
+	fn set(&mut self, key: &str, value: String) {
+		self.0.retain(|(name, _)| !name.eq_ignore_ascii_case(key));
+		self.0.push((key.to_string(), value.into_bytes()));
+	}
+}
+
+/// Carrier reading from binary headers, skipping values that aren't UTF-8
+pub struct BytesExtractor<'a>(pub &'a [(String, Vec<u8>)]);
+
+impl<'a> Extractor for BytesExtractor<'a> {
+	fn get(&self, key: &str) -> Option<&str> {
+		self.0
+			.iter()
+			.find(|(name, _)| name.eq_ignore_ascii_case(key))
+			.and_then(|(_, value)| std::str::from_utf8(value).ok())
+	}
+
+	fn keys(&self) -> Vec<&str> {
+		self.0.iter().map(|(name, _)| name.as_str()).collect()
+	}
+}
+
+/// Writes the context of the current span into `injector` with the configured propagators
+pub fn inject(injector: &mut impl Injector) {
+	let context = Span::current().context();
+
+	global::get_text_map_propagator(|propagator| propagator.inject_context(&context, injector));
+}
+
+/// Reads the remote context from `extractor` with the configured propagators
+///
+/// If the carrier has no span data the propagators default to an unsampled context
+pub fn extract(extractor: &impl Extractor) -> Context {
+	global::get_text_map_propagator(|propagator| propagator.extract(extractor))
+}
+
+/// Starts a consumer span continuing the trace found in `extractor`
+///
+/// The span is parented by the remote context even when another span is current, and starts a
+/// new trace when the carrier has none
+pub fn consumer_span(name: &str, extractor: &impl Extractor) -> Span {
+	crate::traces::with_remote_parent(extract(extractor), || {
+		tracing::info_span!(
+			parent: None,
+			"Message",
+
+			otel.name = %name,
+			otel.kind = %"consumer",
+		)
+	})
+}
+
+/// Starts a consumer span on a new trace, linked to the one found in `extractor`
+///
+/// Fits batches and fan-ins, where a message shouldn't own the trace of its processing
+pub fn linked_consumer_span(name: &str, extractor: &impl Extractor) -> Span {
+	linked_span(name, &extract(extractor))
+}
+
+pub(crate) fn linked_span(name: &str, remote_context: &Context) -> Span {
+	let span = tracing::info_span!(
+		parent: None,
+		"Message",
+
+		otel.name = %name,
+		otel.kind = %"consumer",
+	);
+
+	let remote_span = remote_context.span();
+	let span_context = remote_span.span_context();
+	if span_context.is_valid() {
+		span.add_link(span_context.clone());
+	}
+
+	span
+}
+
+// Gives contexts without a trace a sampled root one, with a trace id from the configured id
+// generator, so that the span created on it has its trace id from the start and logs carry it
+pub(crate) fn create(remote_context: Context) -> Context {
+	if !remote_context.span().span_context().is_valid() {
+		let trace_id = crate::traces::new_trace_id();
+		let span_context = SpanContext::new(
+			trace_id,
+			SpanId::INVALID,
+			TraceFlags::SAMPLED,
+			false,
+			TraceState::default(),
+		);
+
+		remote_context.with_remote_span_context(span_context)
+	} else {
+		remote_context
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::{remote_trace_id, traced, TRACEPARENT};
+	use std::collections::HashMap;
+
+	#[test]
+	fn bytes_carriers_round_trip() {
+		let mut headers = vec![("Traceparent".to_string(), b"stale".to_vec())];
+		BytesInjector(&mut headers).set("traceparent", TRACEPARENT.to_string());
+
+		assert_eq!(headers.len(), 1);
+		assert_eq!(
+			BytesExtractor(&headers).get("traceparent"),
+			Some(TRACEPARENT)
+		);
+	}
+
+	#[test]
+	fn consumer_spans_are_parented_by_the_carrier() {
+		let carrier = HashMap::from([("traceparent".to_string(), TRACEPARENT.to_string())]);
+
+		let trace_id = traced(|| {
+			let _outer = tracing::info_span!("outer").entered();
+			let span = consumer_span("process", &carrier);
+
+			span.context().span().span_context().trace_id()
+		});
+
+		assert_eq!(trace_id, remote_trace_id());
+	}
+
+	#[test]
+	fn linked_consumer_spans_start_a_new_trace() {
+		let carrier = HashMap::from([("traceparent".to_string(), TRACEPARENT.to_string())]);
+
+		let (trace_id, injected) = traced(|| {
+			let span = linked_consumer_span("process", &carrier);
+			let _entered = span.enter();
+
+			let mut injected = HashMap::new();
+			inject(&mut injected);
+
+			(span.context().span().span_context().trace_id(), injected)
+		});
+
+		assert_ne!(trace_id, remote_trace_id());
+		assert!(injected["traceparent"].contains(&trace_id.to_string()));
+	}
+}
@@ -0,0 +1,95 @@
+pub mod mpsc;
+
+use crate::context;
+use opentelemetry::trace::FutureExt;
+use opentelemetry::Context;
+use std::future::Future;
+use tokio::task::JoinHandle;
+use tracing::{Instrument, Span};
+
+/// Spawns `future` on the current span, OpenTelemetry context and log fields
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+	F: Future + Send + 'static,
+	F::Output: Send + 'static,
+{
+	let fields = context::current();
+	let future = context::inherit(fields, future)
+		.instrument(Span::current())
+		.with_current_context();
+
+	tokio::spawn(future)
+}
+
+/// Runs `f` on the blocking pool with the current span, OpenTelemetry context and log fields
+pub fn spawn_blocking<F, R>(f: F) -> JoinHandle<R>
+where
+	F: FnOnce() -> R + Send + 'static,
+	R: Send + 'static,
+{
+	let fields = context::current();
+	let span = Span::current();
+	let otel_context = Context::current();
+
+	tokio::task::spawn_blocking(move || {
+		let _otel_guard = otel_context.attach();
+		let _span_guard = span.enter();
+
+		context::inherit_sync(fields, f)
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	#[tokio::test]
+	async fn spawned_tasks_inherit_the_current_span_and_fields() {
+		let subscriber = tracing_subscriber::registry();
+		let _default = tracing::subscriber::set_default(subscriber);
+
+		let span = tracing::info_span!("parent");
+		let (spawned, fields) = context::with_fields([("job", "reindex")], async {
+			spawn(async { (Span::current().id(), context::current()) })
+				.await
+				.unwrap()
+		})
+		.instrument(span.clone())
+		.await;
+
+		assert_eq!(spawned, span.id());
+		assert_eq!(fields.get("job"), Some(&json!("reindex")));
+	}
+
+	#[tokio::test]
+	async fn tasks_inherit_the_opentelemetry_context() {
+		use opentelemetry::baggage::BaggageExt;
+		use opentelemetry::KeyValue;
+
+		let tenant = || {
+			Context::current()
+				.baggage()
+				.get("tenant.id")
+				.map(|value| value.to_string())
+		};
+		let _attached =
+			Context::current_with_baggage(vec![KeyValue::new("tenant.id", "acme")]).attach();
+
+		let spawned = spawn(async move { tenant() }).await.unwrap();
+		let blocking = spawn_blocking(tenant).await.unwrap();
+
+		assert_eq!(spawned.as_deref(), Some("acme"));
+		assert_eq!(blocking.as_deref(), Some("acme"));
+	}
+
+	#[tokio::test]
+	async fn blocking_tasks_inherit_the_current_fields() {
+		let fields = context::with_fields([("job", "reindex")], async {
+			spawn_blocking(context::current).await.unwrap()
+		})
+		.await;
+
+		assert_eq!(fields.get("job"), Some(&json!("reindex")));
+	}
+}
@@ -0,0 +1,186 @@
+use crate::propagation;
+use opentelemetry::Context;
+use std::time::Instant;
+use tokio::sync::mpsc::{self, error::SendError};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Bounded channel carrying the sender's context along with each message
+///
+/// Reports `channel_queue_depth` and `channel_wait_seconds` labeled by `name`, which is also the
+/// name of the spans started on receive
+pub fn channel<T>(name: &str, buffer: usize) -> (Sender<T>, Receiver<T>) {
+	let (sender, receiver) = mpsc::channel(buffer);
+
+	let sender = Sender {
+		inner: sender,
+		name: name.to_string(),
+	};
+	let receiver = Receiver {
+		inner: receiver,
+		name: name.to_string(),
+	};
+
+	(sender, receiver)
+}
+
+struct Envelope<T> {
+	value: T,
+	context: Context,
+	sent_at: Instant,
+}
+
+/// A received message along with the span for handling it
+pub struct Message<T> {
+	pub value: T,
+	/// Starts a new trace linked to the sender's span
+	pub span: Span,
+}
+
+pub struct Sender<T> {
+	inner: mpsc::Sender<Envelope<T>>,
+	name: String,
+}
+
+impl<T> Clone for Sender<T> {
+	fn clone(&self) -> Self {
+		Sender {
+			inner: self.inner.clone(),
+			name: self.name.clone(),
+		}
+	}
+}
+
+impl<T> Sender<T> {
+	pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+		let envelope = Envelope {
+			value,
+			context: Span::current().context(),
+			sent_at: Instant::now(),
+		};
+
+		// A quick receiver may take the depth below zero for a moment, until the message counts
+		self.inner
+			.send(envelope)
+			.await
+			.map_err(|SendError(envelope)| SendError(envelope.value))?;
+
+		metrics::increment_gauge!("channel_queue_depth", 1.0, "channel" => self.name.clone());
+
+		Ok(())
+	}
+
+	pub fn is_closed(&self) -> bool {
+		self.inner.is_closed()
+	}
+}
+
+pub struct Receiver<T> {
+	inner: mpsc::Receiver<Envelope<T>>,
+	name: String,
+}
+
+impl<T> Receiver<T> {
+	pub async fn recv(&mut self) -> Option<Message<T>> {
+		let envelope = self.inner.recv().await?;
+
+		let wait = envelope.sent_at.elapsed().as_secs_f64();
+		metrics::decrement_gauge!("channel_queue_depth", 1.0, "channel" => self.name.clone());
+		metrics::histogram!("channel_wait_seconds", wait, "channel" => self.name.clone());
+
+		Some(Message {
+			value: envelope.value,
+			span: propagation::linked_span(&self.name, &envelope.context),
+		})
+	}
+
+	pub fn close(&mut self) {
+		self.inner.close()
+	}
+}
+
+impl<T> Drop for Receiver<T> {
+	// Messages left in the channel are dropped along with it
+	fn drop(&mut self) {
+		self.inner.close();
+
+		let mut remaining = 0;
+		while self.inner.try_recv().is_ok() {
+			remaining += 1;
+		}
+
+		if remaining > 0 {
+			metrics::decrement_gauge!("channel_queue_depth", remaining as f64, "channel" => self.name.clone());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::{record_metrics, traced_into, Collect, Metrics};
+	use opentelemetry::trace::TraceContextExt;
+	use tracing::Instrument;
+
+	fn block_on<F: std::future::Future>(future: F) -> F::Output {
+		tokio::runtime::Builder::new_current_thread()
+			.build()
+			.unwrap()
+			.block_on(future)
+	}
+
+	#[test]
+	fn messages_start_a_trace_linked_to_the_sender() {
+		let collect = Collect::default();
+		let sender_context = traced_into(&collect, || {
+			let (sender, mut receiver) = channel("jobs", 1);
+
+			let span = tracing::info_span!("send");
+			let sender_context = span.context().span().span_context().clone();
+			block_on(async {
+				sender.send(1).instrument(span).await.unwrap();
+				receiver.recv().await.unwrap()
+			});
+
+			sender_context
+		});
+
+		let spans = collect.spans();
+		let message = spans.iter().find(|span| span.name == "jobs").unwrap();
+		assert_ne!(message.span_context.trace_id(), sender_context.trace_id());
+		let links: Vec<_> = message
+			.links
+			.iter()
+			.map(|link| &link.span_context)
+			.collect();
+		assert_eq!(links, [&sender_context]);
+	}
+
+	#[test]
+	fn reports_depth_and_wait() {
+		record_metrics();
+		let labels = [("channel", "jobs")];
+
+		let (sender, mut receiver) = channel("jobs", 4);
+		block_on(async {
+			sender.send(1).await.unwrap();
+			sender.send(2).await.unwrap();
+			sender.send(3).await.unwrap();
+			receiver.recv().await.unwrap();
+		});
+
+		let metrics = Metrics::snapshot();
+		assert_eq!(metrics.gauge("channel_queue_depth", &labels), Some(2.0));
+		assert_eq!(metrics.histogram("channel_wait_seconds", &labels).len(), 1);
+
+		drop(receiver);
+		assert!(block_on(sender.send(4)).is_err());
+
+		let metrics = Metrics::snapshot();
+		assert_eq!(
+			metrics.gauge("channel_queue_depth", &labels),
+			Some(0.0),
+			"messages left behind don't count"
+		);
+	}
+}
@@ -0,0 +1,196 @@
+//! Helpers shared by unit tests across modules
+
+use futures::future::{self, BoxFuture};
+use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
+use metrics_util::CompositeKey;
+use opentelemetry::global;
+use opentelemetry::sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry::sdk::propagation::{
+	BaggagePropagator, TextMapCompositePropagator, TraceContextPropagator,
+};
+use opentelemetry::sdk::trace::{Span, SpanProcessor, TracerProvider};
+use opentelemetry::trace::{TraceError, TraceId, TraceResult, TracerProvider as _};
+use opentelemetry::Context;
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::layer::{Identity, SubscriberExt};
+
+pub const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+/// Baggage keys copied onto spans by [`traced`]
+pub const BAGGAGE: &[&str] = &["tenant.id"];
+
+pub fn remote_trace_id() -> TraceId {
+	TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+}
+
+/// Installs the W3C trace context and baggage propagators
+pub fn propagate() {
+	global::set_text_map_propagator(TextMapCompositePropagator::new(vec![
+		Box::new(TraceContextPropagator::new()),
+		Box::new(BaggagePropagator::new()),
+	]));
+}
+
+/// Span processor keeping every span that ends
+#[derive(Clone, Debug, Default)]
+pub struct Collect(pub Arc<Mutex<Vec<SpanData>>>);
+
+impl Collect {
+	pub fn spans(&self) -> Vec<SpanData> {
+		self.0.lock().unwrap().clone()
+	}
+}
+
+impl SpanProcessor for Collect {
+	fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+	fn on_end(&self, span: SpanData) {
+		self.0.lock().unwrap().push(span);
+	}
+
+	fn force_flush(&self) -> TraceResult<()> {
+		Ok(())
+	}
+
+	fn shutdown(&mut self) -> TraceResult<()> {
+		Ok(())
+	}
+}
+
+/// Batch holding a single sampled span named `name`
+pub fn batch(name: &str) -> Vec<SpanData> {
+	crate::otlp_json::decode(&serde_json::json!({
+		"resourceSpans": [{
+			"resource": { "attributes": [] },
+			"scopeSpans": [{
+				"scope": { "name": "test" },
+				"spans": [{
+					"traceId": "0af7651916cd43dd8448eb211c80319c",
+					"spanId": "b7ad6b7169203331",
+					"name": name,
+					"startTimeUnixNano": "1000",
+					"endTimeUnixNano": "2000",
+				}],
+			}],
+		}],
+	}))
+}
+
+/// Span exporter keeping every batch it's sent, unless told to fail or reject them
+#[derive(Clone, Debug, Default)]
+pub struct Exported {
+	spans: Arc<Mutex<Vec<SpanData>>>,
+	pub fail: bool,
+	/// Fails batches as a collector does with the ones it finds invalid
+	pub reject: bool,
+}
+
+impl Exported {
+	pub fn spans(&self) -> Vec<SpanData> {
+		self.spans.lock().unwrap().clone()
+	}
+}
+
+impl SpanExporter for Exported {
+	fn export(&mut self, mut batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+		if self.fail {
+			return Box::pin(future::ready(Err(TraceError::from("unavailable"))));
+		}
+
+		if self.reject {
+			let err = opentelemetry_otlp::Error::Status {
+				code: tonic::Code::InvalidArgument,
+				message: String::from("invalid"),
+			};
+			return Box::pin(future::ready(Err(err.into())));
+		}
+
+		self.spans.lock().unwrap().append(&mut batch);
+		Box::pin(future::ready(Ok(())))
+	}
+}
+
+/// Runs `f` with the OpenTelemetry layer set up as [`crate::init`] does, minus the exporters
+pub fn traced<T>(f: impl FnOnce() -> T) -> T {
+	traced_into(&Collect::default(), f)
+}
+
+/// Same as [`traced`], with the spans ended along the way kept in `collect`
+pub fn traced_into<T>(collect: &Collect, f: impl FnOnce() -> T) -> T {
+	propagate();
+
+	let provider = TracerProvider::builder()
+		.with_span_processor(collect.clone())
+		.build();
+	let tracer = provider.tracer("test");
+	// Other layers follow the OpenTelemetry one in `init`, and tracing-subscriber mixes up the
+	// per-layer filters below a `None` layer when nothing does
+	let subscriber = tracing_subscriber::registry()
+		.with(crate::traces::layer(tracer, BAGGAGE, None))
+		.with(Identity::new());
+
+	tracing::subscriber::with_default(subscriber, f)
+}
+
+/// Records metrics per thread, so that tests only see their own
+pub fn record_metrics() {
+	let _ = DebuggingRecorder::per_thread().install();
+}
+
+/// Metrics recorded on the current thread since [`record_metrics`], histograms are only in the
+/// first snapshot taken after their values were recorded
+pub struct Metrics(Vec<(CompositeKey, DebugValue)>);
+
+impl Metrics {
+	pub fn snapshot() -> Self {
+		let metrics = Snapshotter::current_thread_snapshot()
+			.map(|snapshot| snapshot.into_vec())
+			.unwrap_or_default()
+			.into_iter()
+			.map(|(key, _, _, value)| (key, value))
+			.collect();
+
+		Metrics(metrics)
+	}
+
+	/// Value of the metric with exactly `labels`, in any order
+	pub fn get(&self, name: &str, labels: &[(&str, &str)]) -> Option<&DebugValue> {
+		let mut labels: Vec<(&str, &str)> = labels.to_vec();
+		labels.sort_unstable();
+
+		self.0
+			.iter()
+			.find(|(key, _)| {
+				let key = key.key();
+				let mut recorded: Vec<(&str, &str)> = key
+					.labels()
+					.map(|label| (label.key(), label.value()))
+					.collect();
+				recorded.sort_unstable();
+
+				key.name() == name && recorded == labels
+			})
+			.map(|(_, value)| value)
+	}
+
+	pub fn counter(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+		match self.get(name, labels) {
+			Some(DebugValue::Counter(value)) => *value,
+			_ => 0,
+		}
+	}
+
+	pub fn gauge(&self, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+		match self.get(name, labels) {
+			Some(DebugValue::Gauge(value)) => Some(value.0),
+			_ => None,
+		}
+	}
+
+	pub fn histogram(&self, name: &str, labels: &[(&str, &str)]) -> Vec<f64> {
+		match self.get(name, labels) {
+			Some(DebugValue::Histogram(values)) => values.iter().map(|value| value.0).collect(),
+			_ => Vec::new(),
+		}
+	}
+}
@@ -0,0 +1,114 @@
+use crate::Sub;
+use opentelemetry::baggage::BaggageExt;
+use opentelemetry::trace::SpanKind;
+use opentelemetry::Key;
+use tracing::span::{Attributes, Id};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+/// Allow-listed baggage entries of a span, as `baggage.<key>` fields
+pub struct BaggageFields(pub Vec<(String, String)>);
+
+/// Copies allow-listed baggage entries onto the spans continuing a remote context
+///
+/// Must come after the OpenTelemetry layer, as it reads the context extracted for the span
+pub struct BaggageLayer {
+	allowed: Vec<String>,
+}
+
+impl BaggageLayer {
+	pub fn new(allowed: &[&str]) -> Self {
+		BaggageLayer {
+			allowed: allowed.iter().map(|key| key.to_string()).collect(),
+		}
+	}
+}
+
+impl<S: Sub> Layer<S> for BaggageLayer {
+	fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+		if self.allowed.is_empty() {
+			return;
+		}
+
+		let span = ctx.span(id).unwrap();
+		let mut extensions = span.extensions_mut();
+		let data = match extensions.get_mut::<OtelData>() {
+			Some(data) => data,
+			None => return,
+		};
+
+		let continues_remote = matches!(
+			data.builder.span_kind,
+			Some(SpanKind::Server) | Some(SpanKind::Consumer)
+		);
+		if !continues_remote {
+			return;
+		}
+
+		let baggage = data.parent_cx.baggage();
+		let fields: Vec<(String, String)> = self
+			.allowed
+			.iter()
+			.filter_map(|key| {
+				let value = baggage.get(key.clone())?;
+
+				Some((format!("baggage.{}", key), value.to_string()))
+			})
+			.collect();
+
+		if fields.is_empty() {
+			return;
+		}
+
+		let attributes = data.builder.attributes.get_or_insert_with(Default::default);
+		for (key, value) in &fields {
+			attributes.insert(Key::new(key.clone()), value.clone().into());
+		}
+
+		extensions.insert(BaggageFields(fields));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::propagation::consumer_span;
+	use crate::testing::{traced_into, Collect, TRACEPARENT};
+	use opentelemetry::Key;
+	use std::collections::HashMap;
+
+	#[test]
+	fn copies_allowed_entries_onto_remote_spans() {
+		let carrier = HashMap::from([
+			("traceparent".to_string(), TRACEPARENT.to_string()),
+			(
+				"baggage".to_string(),
+				"tenant.id=acme,region=eu".to_string(),
+			),
+		]);
+
+		let collect = Collect::default();
+		traced_into(&collect, || {
+			let span = consumer_span("process", &carrier);
+			span.in_scope(|| drop(tracing::info_span!("internal")));
+		});
+
+		let spans = collect.spans();
+		let attribute = |name: &str, key: &str| {
+			let span = spans.iter().find(|span| span.name == name).unwrap();
+
+			span.attributes.get(&Key::new(key.to_string())).cloned()
+		};
+
+		assert_eq!(
+			attribute("process", "baggage.tenant.id"),
+			Some("acme".into())
+		);
+		assert_eq!(attribute("process", "baggage.region"), None);
+		assert_eq!(
+			attribute("internal", "baggage.tenant.id"),
+			None,
+			"only spans continuing a remote context get the entries"
+		);
+	}
+}
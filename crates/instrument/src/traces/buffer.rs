@@ -0,0 +1,546 @@
+use super::otlp_json;
+use super::pipeline::HttpStatus;
+use futures::future::BoxFuture;
+use http::StatusCode;
+use opentelemetry::global;
+use opentelemetry::sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry::trace::TraceError;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tonic::Code;
+
+/// Backoff between attempts at the oldest batch, which is retried until it goes through or gets
+/// evicted by the spool's cap
+const RETRY: Retry = Retry {
+	min_backoff: Duration::from_secs(1),
+	max_backoff: Duration::from_secs(60),
+};
+
+#[derive(Debug)]
+struct Retry {
+	min_backoff: Duration,
+	max_backoff: Duration,
+}
+
+/// Spool on local disk holding batches while the exporter is failing
+#[derive(Clone, Copy, Debug)]
+pub struct Buffer<'a> {
+	/// Directory for the spooled batches, each exporter gets its own folder inside it
+	pub directory: &'a str,
+	/// Bytes kept on disk, the oldest batches are evicted to stay under it
+	pub max_bytes: u64,
+}
+
+/// Exporter that spools failed batches to disk and retries them in the background
+///
+/// Batches go straight to disk while older ones are pending, so they reach the collector in order
+#[derive(Debug)]
+pub struct Buffered<E> {
+	shared: Arc<Shared<E>>,
+}
+
+#[derive(Debug)]
+struct Shared<E> {
+	inner: tokio::sync::Mutex<E>,
+	spool: Mutex<Spool>,
+	notify: Notify,
+	retry: Retry,
+	closed: AtomicBool,
+	// Cuts the backoff short on shutdown
+	closing: Notify,
+}
+
+impl<E: SpanExporter + 'static> Buffered<E> {
+	pub fn new(inner: E, buffer: &Buffer, name: &str) -> Self {
+		let spool = Spool::open(Path::new(buffer.directory), name, buffer.max_bytes);
+
+		Self::start(inner, spool, RETRY)
+	}
+
+	fn start(inner: E, spool: Spool, retry: Retry) -> Self {
+		let shared = Arc::new(Shared {
+			inner: tokio::sync::Mutex::new(inner),
+			spool: Mutex::new(spool),
+			notify: Notify::new(),
+			retry,
+			closed: AtomicBool::new(false),
+			closing: Notify::new(),
+		});
+
+		tokio::spawn(drain(shared.clone()));
+
+		Buffered { shared }
+	}
+}
+
+impl<E: SpanExporter + 'static> SpanExporter for Buffered<E> {
+	fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+		let shared = self.shared.clone();
+
+		Box::pin(async move {
+			let backlog = !shared.spool.lock().unwrap().is_empty();
+			let mut pending = Pending {
+				shared: &shared,
+				batch: Some(batch),
+			};
+
+			if !backlog {
+				let batch = pending.batch.clone().unwrap_or_default();
+				let export = shared.inner.lock().await.export(batch);
+
+				match export.await {
+					Ok(()) => pending.batch = None,
+					Err(err) => global::handle_error(err),
+				}
+			}
+
+			Ok(())
+		})
+	}
+
+	// The drain gives the spool a last attempt and shuts the inner exporter down once it's done
+	fn shutdown(&mut self) {
+		self.shared.closed.store(true, Ordering::Release);
+		self.shared.notify.notify_one();
+		self.shared.closing.notify_one();
+	}
+}
+
+// Spools the batch on drop so that exports cancelled by the processor's timeout aren't lost
+struct Pending<'a, E> {
+	shared: &'a Shared<E>,
+	batch: Option<Vec<SpanData>>,
+}
+
+impl<E> Drop for Pending<'_, E> {
+	fn drop(&mut self) {
+		if let Some(batch) = self.batch.take() {
+			self.shared.spool.lock().unwrap().push(&batch);
+			self.shared.notify.notify_one();
+		}
+	}
+}
+
+async fn drain<E: SpanExporter>(shared: Arc<Shared<E>>) {
+	let retry = &shared.retry;
+	let mut backoff = retry.min_backoff;
+
+	loop {
+		if shared.closed.load(Ordering::Acquire) {
+			flush(&shared).await;
+			shared.inner.lock().await.shutdown();
+			return;
+		}
+
+		let oldest = shared.spool.lock().unwrap().oldest();
+		let Some((segment, batch)) = oldest else {
+			shared.notify.notified().await;
+			continue;
+		};
+
+		if send(&shared, segment, batch).await {
+			backoff = retry.min_backoff;
+		} else {
+			tokio::select! {
+				_ = tokio::time::sleep(backoff) => {}
+				_ = shared.closing.notified() => {}
+			}
+			backoff = (backoff * 2).min(retry.max_backoff);
+		}
+	}
+}
+
+// Gives every spooled batch one more attempt, what still fails is left on disk for the next run
+async fn flush<E: SpanExporter>(shared: &Shared<E>) {
+	loop {
+		let oldest = shared.spool.lock().unwrap().oldest();
+		let Some((segment, batch)) = oldest else {
+			return;
+		};
+
+		if !send(shared, segment, batch).await {
+			return;
+		}
+	}
+}
+
+// Exports the oldest segment, false when it failed and is worth retrying
+async fn send<E: SpanExporter>(shared: &Shared<E>, segment: Segment, batch: Vec<SpanData>) -> bool {
+	let export = shared.inner.lock().await.export(batch);
+	match export.await {
+		Ok(()) => {
+			shared.spool.lock().unwrap().remove(segment);
+			true
+		}
+		// Retrying can't get a batch through once the collector found it invalid, and it would
+		// hold back the others
+		Err(err) if rejected(&err) => {
+			global::handle_error(err);
+
+			let mut spool = shared.spool.lock().unwrap();
+			if spool.remove(segment) {
+				spool.dropped(segment.records);
+			}
+			true
+		}
+		Err(err) => {
+			global::handle_error(err);
+			false
+		}
+	}
+}
+
+/// Whether the collector refused the batch itself, rather than failing to take it for now
+///
+/// Over HTTP that's any client error but timeouts and throttling, which are worth another try
+fn rejected(err: &TraceError) -> bool {
+	match err {
+		TraceError::ExportFailed(err) => {
+			let err: &(dyn Error + 'static) = err.as_ref();
+
+			matches!(
+				err.downcast_ref::<opentelemetry_otlp::Error>(),
+				Some(opentelemetry_otlp::Error::Status {
+					code: Code::InvalidArgument,
+					..
+				})
+			)
+		}
+		TraceError::Other(err) => {
+			err.downcast_ref::<HttpStatus>()
+				.is_some_and(|HttpStatus(status)| {
+					status.is_client_error()
+						&& *status != StatusCode::REQUEST_TIMEOUT
+						&& *status != StatusCode::TOO_MANY_REQUESTS
+				})
+		}
+		_ => false,
+	}
+}
+
+#[derive(Debug)]
+struct Spool {
+	directory: PathBuf,
+	name: String,
+	max_bytes: u64,
+	bytes: u64,
+	next: u64,
+	segments: VecDeque<Segment>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Segment {
+	sequence: u64,
+	records: usize,
+	bytes: u64,
+}
+
+impl Segment {
+	fn file_name(&self) -> String {
+		format!("{:020}-{}.json", self.sequence, self.records)
+	}
+
+	fn parse(file_name: &str) -> Option<(u64, usize)> {
+		let (sequence, records) = file_name.strip_suffix(".json")?.split_once('-')?;
+
+		Some((sequence.parse().ok()?, records.parse().ok()?))
+	}
+}
+
+impl Spool {
+	// Picks up the segments left behind by a previous run
+	fn open(root: &Path, name: &str, max_bytes: u64) -> Self {
+		let folder: String = name
+			.chars()
+			.map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+			.collect();
+		let directory = root.join(folder);
+		fs::create_dir_all(&directory).expect("Unable to create trace buffer directory");
+
+		let mut segments: Vec<Segment> = fs::read_dir(&directory)
+			.expect("Unable to read trace buffer directory")
+			.filter_map(|entry| {
+				let entry = entry.ok()?;
+				let (sequence, records) = Segment::parse(entry.file_name().to_str()?)?;
+				let bytes = entry.metadata().ok()?.len();
+
+				Some(Segment {
+					sequence,
+					records,
+					bytes,
+				})
+			})
+			.collect();
+		segments.sort_by_key(|segment| segment.sequence);
+
+		let mut spool = Spool {
+			directory,
+			name: name.to_string(),
+			max_bytes,
+			bytes: segments.iter().map(|segment| segment.bytes).sum(),
+			next: segments.last().map_or(0, |segment| segment.sequence + 1),
+			segments: segments.into(),
+		};
+		spool.evict();
+
+		spool
+	}
+
+	fn is_empty(&self) -> bool {
+		self.segments.is_empty()
+	}
+
+	fn push(&mut self, batch: &[SpanData]) {
+		let line = otlp_json::encode(batch).to_string();
+		let segment = Segment {
+			sequence: self.next,
+			records: batch.len(),
+			bytes: line.len() as u64,
+		};
+
+		if let Err(err) = fs::write(self.path(&segment), line) {
+			global::handle_error(TraceError::Other(Box::new(err)));
+			self.evicted(segment.records);
+			return;
+		}
+
+		self.next += 1;
+		self.bytes += segment.bytes;
+		self.segments.push_back(segment);
+		self.evict();
+	}
+
+	fn oldest(&mut self) -> Option<(Segment, Vec<SpanData>)> {
+		while let Some(segment) = self.segments.front().copied() {
+			match self.read(&segment) {
+				Ok(batch) => return Some((segment, batch)),
+				Err(err) => {
+					global::handle_error(TraceError::Other(Box::new(err)));
+					self.remove(segment);
+					self.evicted(segment.records);
+				}
+			}
+		}
+
+		None
+	}
+
+	// No-op when the segment got evicted while it was being exported
+	fn remove(&mut self, segment: Segment) -> bool {
+		if self.segments.front() != Some(&segment) {
+			return false;
+		}
+
+		self.segments.pop_front();
+		self.bytes -= segment.bytes;
+		let _ = fs::remove_file(self.path(&segment));
+		self.report();
+
+		true
+	}
+
+	fn evict(&mut self) {
+		while self.bytes > self.max_bytes {
+			let Some(segment) = self.segments.pop_front() else {
+				break;
+			};
+
+			self.bytes -= segment.bytes;
+			let _ = fs::remove_file(self.path(&segment));
+			self.evicted(segment.records);
+		}
+
+		self.report();
+	}
+
+	fn read(&self, segment: &Segment) -> io::Result<Vec<SpanData>> {
+		let content = fs::read(self.path(segment))?;
+		let json = serde_json::from_slice(&content)?;
+
+		Ok(otlp_json::decode(&json))
+	}
+
+	fn path(&self, segment: &Segment) -> PathBuf {
+		self.directory.join(segment.file_name())
+	}
+
+	fn evicted(&self, records: usize) {
+		let labels = [("exporter", self.name.clone())];
+		metrics::counter!("buffer_records_evicted_total", records as u64, &labels);
+	}
+
+	fn dropped(&self, records: usize) {
+		let labels = [("exporter", self.name.clone())];
+		metrics::counter!("buffer_records_dropped_total", records as u64, &labels);
+	}
+
+	fn report(&self) {
+		let labels = [("exporter", self.name.clone())];
+		metrics::gauge!("buffer_bytes", self.bytes as f64, &labels);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::{batch, record_metrics, Exported, Metrics};
+	use crate::traces::pipeline::{otlp, Exporter, Transport};
+	use axum::routing::post;
+	use axum::Router;
+	use std::sync::atomic::AtomicU16;
+
+	#[test]
+	fn evicts_oldest_segments_over_the_cap() {
+		let root = std::env::temp_dir().join(format!("instrument-buffer-{}", std::process::id()));
+		let name = "http://collector:4317";
+
+		let mut spool = Spool::open(&root, name, 0);
+		spool.push(&batch("span-0"));
+		assert!(spool.is_empty(), "segments over the cap are evicted");
+
+		let files = fs::read_dir(root.join("http___collector_4317"))
+			.unwrap()
+			.count();
+		assert_eq!(files, 0, "evicted segments are removed from disk");
+
+		spool.max_bytes = otlp_json::encode(&batch("span-1")).to_string().len() as u64 * 2;
+		for name in ["span-1", "span-2", "span-3"] {
+			spool.push(&batch(name));
+		}
+
+		let (_, oldest) = spool.oldest().expect("segments under the cap are kept");
+		assert_eq!(oldest[0].name, "span-2");
+		assert_eq!(spool.segments.len(), 2);
+
+		let reopened = Spool::open(&root, name, spool.max_bytes);
+		assert_eq!(reopened.segments, spool.segments);
+		assert_eq!(reopened.next, spool.next);
+
+		fs::remove_dir_all(root).unwrap();
+	}
+
+	#[tokio::test]
+	async fn retries_failed_batches_until_they_go_through() {
+		let root = std::env::temp_dir().join(format!("instrument-retry-{}", std::process::id()));
+		let mut spool = Spool::open(&root, "test", u64::MAX);
+		spool.push(&batch("span-0"));
+
+		let mut exported = Exported::default();
+		exported.fail = true;
+		let buffered = Buffered::start(exported, spool, retry());
+
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		assert!(
+			!buffered.shared.spool.lock().unwrap().is_empty(),
+			"failed batches stay spooled"
+		);
+
+		buffered.shared.inner.lock().await.fail = false;
+		until_drained(&buffered).await;
+		assert_eq!(buffered.shared.inner.lock().await.spans()[0].name, "span-0");
+
+		fs::remove_dir_all(root).unwrap();
+	}
+
+	#[tokio::test]
+	async fn drops_batches_the_collector_rejects() {
+		record_metrics();
+		let root = std::env::temp_dir().join(format!("instrument-reject-{}", std::process::id()));
+		let mut spool = Spool::open(&root, "test", u64::MAX);
+		spool.push(&batch("span-0"));
+
+		let mut exported = Exported::default();
+		exported.reject = true;
+		let buffered = Buffered::start(exported, spool, retry());
+		until_drained(&buffered).await;
+
+		let labels = [("exporter", "test")];
+		assert_eq!(
+			Metrics::snapshot().counter("buffer_records_dropped_total", &labels),
+			1
+		);
+		assert_eq!(fs::read_dir(root.join("test")).unwrap().count(), 0);
+
+		fs::remove_dir_all(root).unwrap();
+	}
+
+	#[tokio::test]
+	async fn http_client_errors_are_rejections() {
+		let status = Arc::new(AtomicU16::new(200));
+		let respond = status.clone();
+		let app = Router::new().route(
+			"/v1/traces",
+			post(move || {
+				let status = respond.load(Ordering::Relaxed);
+				async move { StatusCode::from_u16(status).unwrap() }
+			}),
+		);
+		let server = axum::Server::bind(&([127, 0, 0, 1], 0).into()).serve(app.into_make_service());
+		let endpoint = format!("http://{}", server.local_addr());
+		tokio::spawn(server);
+
+		let mut exporter = otlp(&Exporter {
+			endpoint: &endpoint,
+			transport: Transport::HttpProtobuf,
+			..Exporter::LOCAL
+		});
+
+		for (code, rejection) in [
+			(400, true),
+			(413, true),
+			(408, false),
+			(429, false),
+			(503, false),
+		] {
+			status.store(code, Ordering::Relaxed);
+			let err = exporter.export(batch("span-0")).await.unwrap_err();
+			assert_eq!(rejected(&err), rejection, "{code}");
+		}
+
+		status.store(200, Ordering::Relaxed);
+		exporter.export(batch("span-0")).await.unwrap();
+	}
+
+	#[tokio::test]
+	async fn shutdown_flushes_the_spool_and_stops_the_drain() {
+		let root = std::env::temp_dir().join(format!("instrument-shutdown-{}", std::process::id()));
+		let mut spool = Spool::open(&root, "test", u64::MAX);
+		spool.push(&batch("span-0"));
+
+		let mut exported = Exported::default();
+		exported.fail = true;
+		let mut buffered = Buffered::start(exported, spool, RETRY);
+		tokio::time::sleep(Duration::from_millis(50)).await;
+
+		buffered.shared.inner.lock().await.fail = false;
+		buffered.shutdown();
+		while Arc::strong_count(&buffered.shared) > 1 {
+			tokio::time::sleep(Duration::from_millis(1)).await;
+		}
+
+		assert!(buffered.shared.spool.lock().unwrap().is_empty());
+		assert_eq!(buffered.shared.inner.lock().await.spans()[0].name, "span-0");
+
+		fs::remove_dir_all(root).unwrap();
+	}
+
+	fn retry() -> Retry {
+		Retry {
+			min_backoff: Duration::from_millis(1),
+			max_backoff: Duration::from_millis(1),
+		}
+	}
+
+	async fn until_drained<E>(buffered: &Buffered<E>) {
+		while !buffered.shared.spool.lock().unwrap().is_empty() {
+			tokio::time::sleep(Duration::from_millis(1)).await;
+		}
+	}
+}
@@ -0,0 +1,146 @@
+use once_cell::sync::Lazy;
+use opentelemetry::global;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Window in which repeats of the same error are only counted
+const WINDOW: Duration = Duration::from_secs(60);
+/// Distinct errors tracked at once, any others share a single window until expired ones make room
+const TRACKED: usize = 256;
+
+struct Seen {
+	since: Instant,
+	suppressed: u64,
+}
+
+impl Seen {
+	fn new(now: Instant) -> Self {
+		Seen {
+			since: now,
+			suppressed: 0,
+		}
+	}
+
+	// Counts a repeat inside the window, or starts the next one handing back the repeats counted
+	fn repeat(&mut self, now: Instant) -> Option<u64> {
+		if now.duration_since(self.since) < WINDOW {
+			self.suppressed += 1;
+			return None;
+		}
+
+		let suppressed = self.suppressed;
+		self.since = now;
+		self.suppressed = 0;
+
+		Some(suppressed)
+	}
+}
+
+/// Repeats of each message seen in the last [`WINDOW`]
+#[derive(Default)]
+pub(crate) struct Limiter {
+	seen: HashMap<String, Seen>,
+	overflow: Option<Seen>,
+}
+
+impl Limiter {
+	/// Whether `message` should be logged at `now`, along with the repeats suppressed since it last
+	/// was
+	pub(crate) fn check(&mut self, message: &str, now: Instant) -> Option<u64> {
+		let seen = &mut self.seen;
+
+		if let Some(entry) = seen.get_mut(message) {
+			return entry.repeat(now);
+		}
+
+		if seen.len() >= TRACKED {
+			seen.retain(|_, entry| now.duration_since(entry.since) < WINDOW);
+		}
+		if seen.len() < TRACKED {
+			seen.insert(message.to_string(), Seen::new(now));
+			return Some(0);
+		}
+
+		match &mut self.overflow {
+			Some(overflow) => overflow.repeat(now),
+			None => {
+				self.overflow = Some(Seen::new(now));
+				Some(0)
+			}
+		}
+	}
+}
+
+static SEEN: Lazy<Mutex<Limiter>> = Lazy::new(Default::default);
+
+/// Logs errors raised inside the OpenTelemetry SDK, e.g. failed exports, as structured events
+pub fn install() {
+	global::set_error_handler(|error| report(error.to_string()))
+		.expect("Unable to register OpenTelemetry error handler");
+}
+
+fn report(message: String) {
+	let suppressed = SEEN
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner())
+		.check(&message, Instant::now());
+
+	if let Some(suppressed) = suppressed {
+		tracing::error!(component = "otel-sdk", suppressed, "{}", message);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn repeats_are_counted_until_the_window_ends() {
+		let mut limiter = Limiter::default();
+		let start = Instant::now();
+
+		assert_eq!(limiter.check("export failed", start), Some(0));
+		assert_eq!(limiter.check("export failed", start + WINDOW / 2), None);
+		assert_eq!(limiter.check("export failed", start + WINDOW / 2), None);
+		assert_eq!(
+			limiter.check("other", start + WINDOW / 2),
+			Some(0),
+			"errors are limited on their own"
+		);
+
+		assert_eq!(limiter.check("export failed", start + WINDOW), Some(2));
+		assert_eq!(
+			limiter.check("export failed", start + WINDOW * 2),
+			Some(0),
+			"the count starts over with each window"
+		);
+	}
+
+	#[test]
+	fn errors_beyond_the_tracked_ones_share_a_window() {
+		let mut limiter = Limiter::default();
+		let start = Instant::now();
+
+		for index in 0..TRACKED {
+			limiter.check(&index.to_string(), start);
+		}
+
+		assert_eq!(limiter.check("untracked", start), Some(0));
+		assert_eq!(limiter.check("untracked", start), None);
+		assert_eq!(
+			limiter.check("other", start + WINDOW / 2),
+			None,
+			"untracked errors are limited together"
+		);
+
+		let later = start + WINDOW;
+		assert_eq!(limiter.check("tracked", later), Some(0));
+		assert_eq!(
+			limiter.check("tracked", later),
+			None,
+			"expired errors make room"
+		);
+		assert_eq!(limiter.check("0", later), Some(0), "and are forgotten");
+	}
+}
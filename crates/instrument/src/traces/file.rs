@@ -0,0 +1,36 @@
+use super::otlp_json;
+use futures::future::{self, BoxFuture};
+use opentelemetry::sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry::trace::TraceError;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// Appends every exported batch to a file as a line of OTLP-JSON
+#[derive(Debug)]
+pub struct FileExporter {
+	file: File,
+}
+
+impl FileExporter {
+	pub fn new(path: &Path) -> Self {
+		let file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(path)
+			.expect("Unable to open trace export file");
+
+		FileExporter { file }
+	}
+}
+
+impl SpanExporter for FileExporter {
+	fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+		let line = otlp_json::encode(&batch).to_string();
+		let result = writeln!(self.file, "{}", line)
+			.and_then(|_| self.file.flush())
+			.map_err(|err| TraceError::Other(Box::new(err)));
+
+		Box::pin(future::ready(result))
+	}
+}
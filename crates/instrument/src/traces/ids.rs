@@ -0,0 +1,175 @@
+use once_cell::sync::OnceCell;
+use opentelemetry::sdk::trace::IdGenerator as _;
+use opentelemetry::sdk::trace::{self as sdktrace, RandomIdGenerator, XrayIdGenerator};
+use opentelemetry::trace::{SpanId, TraceId};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static GENERATOR: OnceCell<(IdGenerator, Box<dyn sdktrace::IdGenerator>)> = OnceCell::new();
+
+/// Source of trace and span ids, for spans and for traces started by incoming requests
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IdGenerator {
+	#[default]
+	Random,
+	/// Trace ids prefixed by the epoch seconds, as AWS X-Ray and its load balancers expect
+	XRay,
+	/// Same sequence of ids on every run, for snapshot tests of log output
+	Seeded(u64),
+}
+
+impl FromStr for IdGenerator {
+	type Err = String;
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value.trim() {
+			"random" => Ok(IdGenerator::Random),
+			"xray" => Ok(IdGenerator::XRay),
+			other => match other.strip_prefix("seeded:") {
+				Some(seed) => seed
+					.parse()
+					.map(IdGenerator::Seeded)
+					.map_err(|_| format!("invalid id generator seed: {}", seed)),
+				None => Err(format!("unknown id generator: {}", other)),
+			},
+		}
+	}
+}
+
+impl IdGenerator {
+	fn build(&self) -> Box<dyn sdktrace::IdGenerator> {
+		match self {
+			IdGenerator::Random => Box::<RandomIdGenerator>::default(),
+			IdGenerator::XRay => Box::<XrayIdGenerator>::default(),
+			IdGenerator::Seeded(seed) => Box::new(SeededIdGenerator::new(*seed)),
+		}
+	}
+}
+
+/// Makes `generator` the one behind [`Configured`]
+///
+/// Ids drawn before a switch would no longer follow the configured scheme, so installing another
+/// generator than the first one panics
+pub fn install(generator: &IdGenerator) {
+	let (installed, _) = GENERATOR.get_or_init(|| (*generator, generator.build()));
+
+	assert_eq!(
+		installed, generator,
+		"Id generator is already installed as {:?}",
+		installed
+	);
+}
+
+pub fn new_trace_id() -> TraceId {
+	Configured.new_trace_id()
+}
+
+/// Delegates to the installed generator, falling back to random ids before it's installed
+///
+/// Shared by the tracer and [`crate::propagation`] so both draw from the same sequence
+#[derive(Clone, Copy, Debug)]
+pub struct Configured;
+
+impl sdktrace::IdGenerator for Configured {
+	fn new_trace_id(&self) -> TraceId {
+		match GENERATOR.get() {
+			Some((_, generator)) => generator.new_trace_id(),
+			None => RandomIdGenerator::default().new_trace_id(),
+		}
+	}
+
+	fn new_span_id(&self) -> SpanId {
+		match GENERATOR.get() {
+			Some((_, generator)) => generator.new_span_id(),
+			None => RandomIdGenerator::default().new_span_id(),
+		}
+	}
+}
+
+// SplitMix64, the order of ids across threads still depends on scheduling
+#[derive(Debug)]
+struct SeededIdGenerator {
+	state: AtomicU64,
+}
+
+impl SeededIdGenerator {
+	fn new(seed: u64) -> Self {
+		SeededIdGenerator {
+			state: AtomicU64::new(seed),
+		}
+	}
+
+	fn next(&self) -> u64 {
+		let mut z = self
+			.state
+			.fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed)
+			.wrapping_add(0x9e37_79b9_7f4a_7c15);
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+		z ^ (z >> 31)
+	}
+}
+
+impl sdktrace::IdGenerator for SeededIdGenerator {
+	fn new_trace_id(&self) -> TraceId {
+		let id = (u128::from(self.next()) << 64) | u128::from(self.next());
+
+		TraceId::from_bytes(id.max(1).to_be_bytes())
+	}
+
+	fn new_span_id(&self) -> SpanId {
+		SpanId::from_bytes(self.next().max(1).to_be_bytes())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use opentelemetry::trace::TraceContextExt;
+	use opentelemetry::Context;
+	use std::time::{SystemTime, UNIX_EPOCH};
+
+	#[test]
+	fn seeded_ids_repeat_across_runs() {
+		let ids = |seed| {
+			let generator = IdGenerator::Seeded(seed).build();
+			(generator.new_trace_id(), generator.new_span_id())
+		};
+
+		assert_eq!(ids(42), ids(42));
+		assert_ne!(ids(42), ids(43));
+		assert_eq!("seeded:42".parse(), Ok(IdGenerator::Seeded(42)));
+	}
+
+	// Other tests never install one, and any generator keeps their ids valid
+	#[test]
+	fn requests_without_a_trace_draw_from_the_configured_generator() {
+		install(&IdGenerator::XRay);
+		install(&IdGenerator::XRay);
+		let second = std::panic::catch_unwind(|| install(&IdGenerator::Random));
+		assert!(second.is_err(), "switching generators is rejected");
+
+		let created = crate::propagation::create(Context::new());
+		assert!(started_now(created.span().span_context().trace_id()));
+	}
+
+	#[test]
+	fn xray_trace_ids_start_with_the_epoch_seconds() {
+		let generator = IdGenerator::XRay.build();
+
+		assert!(started_now(generator.new_trace_id()));
+	}
+
+	// Whether the first 4 bytes of `trace_id` are the current epoch seconds
+	fn started_now(trace_id: TraceId) -> bool {
+		let bytes = trace_id.to_bytes();
+		let seconds = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap()
+			.as_secs();
+
+		now.abs_diff(u64::from(seconds)) <= 1
+	}
+}
@@ -0,0 +1,211 @@
+use crate::{logs, Sub};
+use opentelemetry::sdk::trace as sdktrace;
+use opentelemetry::{Array, Key, Value};
+use std::error::Error;
+use std::fmt;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+/// Bounds of what a span may carry, anything above them is dropped by the SDK
+#[derive(Clone, Debug)]
+pub struct Limits {
+	pub attributes_per_span: u32,
+	pub events_per_span: u32,
+	pub links_per_span: u32,
+	pub attributes_per_event: u32,
+	pub attributes_per_link: u32,
+	/// Maximum bytes of string attributes, longer ones are cut to it, marker included
+	pub attribute_length: Option<usize>,
+}
+
+impl Default for Limits {
+	fn default() -> Self {
+		Limits {
+			attributes_per_span: 128,
+			events_per_span: 128,
+			links_per_span: 128,
+			attributes_per_event: 128,
+			attributes_per_link: 128,
+			attribute_length: None,
+		}
+	}
+}
+
+impl Limits {
+	pub fn apply(&self, config: sdktrace::Config) -> sdktrace::Config {
+		config
+			.with_max_attributes_per_span(self.attributes_per_span)
+			.with_max_events_per_span(self.events_per_span)
+			.with_max_links_per_span(self.links_per_span)
+			.with_max_attributes_per_event(self.attributes_per_event)
+			.with_max_attributes_per_link(self.attributes_per_link)
+	}
+}
+
+/// Cuts long string attributes recorded by `tracing_opentelemetry`, marking them as the log
+/// fields are
+///
+/// Events never reach that layer, so only span fields need it. Must come after the OpenTelemetry
+/// layer, as it rewrites what that layer recorded
+pub struct TruncationLayer {
+	length: usize,
+}
+
+impl TruncationLayer {
+	pub fn new(length: usize) -> Self {
+		TruncationLayer { length }
+	}
+
+	// Truncates the attributes under `keys`, or all of them when `None`
+	fn truncate_span<S: Sub>(&self, id: &Id, ctx: &Context<'_, S>, keys: Option<&[Key]>) {
+		let span = ctx.span(id).unwrap();
+		let mut extensions = span.extensions_mut();
+		let data = match extensions.get_mut::<OtelData>() {
+			Some(data) => data,
+			None => return,
+		};
+
+		let Some(attributes) = data.builder.attributes.as_mut() else {
+			return;
+		};
+		match keys {
+			Some(keys) => {
+				for key in keys {
+					if let Some(value) = attributes.get_mut(key) {
+						truncate_value(value, self.length);
+					}
+				}
+			}
+			None => {
+				for value in attributes.values_mut() {
+					truncate_value(value, self.length);
+				}
+			}
+		}
+	}
+}
+
+impl<S: Sub> Layer<S> for TruncationLayer {
+	fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+		self.truncate_span(id, &ctx, None);
+	}
+
+	fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+		let mut recorded = Recorded::default();
+		values.record(&mut recorded);
+
+		self.truncate_span(id, &ctx, Some(&recorded.0));
+	}
+}
+
+/// Attribute keys written for the fields of a record
+#[derive(Default)]
+struct Recorded(Vec<Key>);
+
+impl Visit for Recorded {
+	fn record_debug(&mut self, field: &Field, _value: &dyn fmt::Debug) {
+		self.0.push(Key::new(field.name()));
+	}
+
+	// Errors are propagated as exception attributes as well
+	fn record_error(&mut self, field: &Field, _value: &(dyn Error + 'static)) {
+		self.0.push(Key::new(field.name()));
+		self.0.push(Key::new("exception.message"));
+		self.0.push(Key::new("exception.stacktrace"));
+	}
+}
+
+fn truncate_value(value: &mut Value, length: usize) {
+	match value {
+		Value::String(string) if string.as_str().len() > length => {
+			*string = cut(string.as_str(), length).into();
+		}
+		Value::Array(Array::String(strings)) => {
+			for string in strings
+				.iter_mut()
+				.filter(|string| string.as_str().len() > length)
+			{
+				*string = cut(string.as_str(), length).into();
+			}
+		}
+		_ => {}
+	}
+}
+
+// Same cut and marker as log fields, the marker is left out when the limit can't hold it
+fn cut(string: &str, length: usize) -> String {
+	let mut string = string.to_string();
+	logs::truncate(&mut string, length);
+
+	string
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::Collect;
+	use opentelemetry::sdk::trace::TracerProvider;
+	use opentelemetry::trace::TracerProvider as _;
+	use tracing::field::Empty;
+	use tracing_subscriber::layer::{Identity, SubscriberExt};
+
+	#[test]
+	fn long_strings_are_cut() {
+		let mut value = Value::from("a".repeat(40));
+		truncate_value(&mut value, 30);
+		assert_eq!(value.as_str(), "aaaaaaa...(truncated 33 bytes)");
+
+		let mut value = Value::from("a".repeat(20));
+		truncate_value(&mut value, 8);
+		assert_eq!(
+			value.as_str(),
+			"aaaaaaaa",
+			"the marker is left out when it doesn't fit"
+		);
+
+		let mut value = Value::Array(Array::String(vec!["short".into(), "a".repeat(10).into()]));
+		truncate_value(&mut value, 8);
+		assert_eq!(
+			value,
+			Value::Array(Array::String(vec!["short".into(), "aaaaaaaa".into()]))
+		);
+
+		let mut value = Value::from("ééé");
+		truncate_value(&mut value, 5);
+		assert_eq!(value.as_str(), "éé", "cuts on char boundaries");
+
+		let mut value = Value::I64(1_000_000_000);
+		truncate_value(&mut value, 2);
+		assert_eq!(value, Value::I64(1_000_000_000));
+	}
+
+	#[test]
+	fn recorded_fields_are_cut() {
+		let collect = Collect::default();
+		let provider = TracerProvider::builder()
+			.with_span_processor(collect.clone())
+			.build();
+		let subscriber = tracing_subscriber::registry()
+			.with(crate::traces::layer(provider.tracer("test"), &[], Some(30)))
+			.with(Identity::new());
+
+		tracing::subscriber::with_default(subscriber, || {
+			let span = tracing::info_span!("span", created = %"a".repeat(40), recorded = Empty);
+			span.record("recorded", "b".repeat(40));
+		});
+
+		let spans = collect.spans();
+		let attribute = |key: &'static str| spans[0].attributes.get(&Key::new(key)).cloned();
+		assert_eq!(
+			attribute("created"),
+			Some(Value::from("aaaaaaa...(truncated 33 bytes)"))
+		);
+		assert_eq!(
+			attribute("recorded"),
+			Some(Value::from("bbbbbbb...(truncated 33 bytes)"))
+		);
+	}
+}
@@ -0,0 +1,179 @@
+mod baggage;
+mod buffer;
+mod errors;
+mod file;
+mod ids;
+mod limits;
+pub mod otlp_json;
+mod pipeline;
+mod propagators;
+mod remote;
+mod retention;
+mod span_metrics;
+mod stdout;
+
+pub use self::baggage::BaggageFields;
+use self::baggage::BaggageLayer;
+pub use self::buffer::Buffer;
+pub(crate) use self::errors::Limiter;
+pub(crate) use self::ids::new_trace_id;
+pub use self::ids::IdGenerator;
+pub use self::limits::Limits;
+use self::limits::TruncationLayer;
+pub use self::pipeline::{Batch, Exporter, Transport};
+pub use self::propagators::Propagator;
+pub(crate) use self::remote::with_remote_parent;
+use self::remote::RemoteParentLayer;
+pub use self::retention::Retention;
+use self::retention::{RecordDropped, Retaining};
+use self::span_metrics::SpanMetricsLayer;
+use super::Sub;
+
+use opentelemetry::global;
+use opentelemetry::sdk::trace as sdktrace;
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_semantic_conventions as semcov;
+use tracing_subscriber::filter;
+
+use tracing_subscriber::Layer;
+
+pub struct Options<'a> {
+	pub service: &'a str,
+	pub version: &'a str,
+	pub exporters: &'a [Exporter<'a>],
+	pub propagators: &'a [Propagator],
+	pub limits: &'a Limits,
+	pub id_generator: IdGenerator,
+	pub retention: Option<&'a Retention>,
+}
+
+pub fn init(opts: Options) -> sdktrace::Tracer {
+	errors::install();
+	ids::install(&opts.id_generator);
+	global::set_text_map_propagator(propagators::composite(opts.propagators));
+
+	let resource = Resource::new(vec![
+		semcov::resource::SERVICE_NAME.string(opts.service.to_string()),
+		semcov::resource::SERVICE_VERSION.string(opts.version.to_string()),
+	]);
+
+	let config = opts
+		.limits
+		.apply(sdktrace::config().with_resource(resource))
+		.with_id_generator(ids::Configured);
+
+	let provider = provider(opts.exporters, opts.retention, config);
+	let tracer = provider.versioned_tracer("instrument", Some(env!("CARGO_PKG_VERSION")), None);
+	global::set_tracer_provider(provider);
+
+	tracer
+}
+
+/// Provider sending spans through a processor of their own to each of the `exporters`
+fn provider(
+	exporters: &[Exporter],
+	retention: Option<&Retention>,
+	config: sdktrace::Config,
+) -> sdktrace::TracerProvider {
+	let processors = exporters.iter().map(pipeline::processor);
+	match retention {
+		// Spans the configured sampler drops are recorded too, so that failed traces can be kept
+		Some(retention) => {
+			let sampler = RecordDropped(config.sampler.clone());
+
+			sdktrace::TracerProvider::builder()
+				.with_span_processor(Retaining::new(processors.collect(), retention))
+				.with_config(config.with_sampler(sampler))
+		}
+		None => processors
+			.fold(sdktrace::TracerProvider::builder(), |builder, processor| {
+				builder.with_span_processor(processor)
+			})
+			.with_config(config),
+	}
+	.build()
+}
+
+pub fn layer<S: Sub>(
+	tracer: sdktrace::Tracer,
+	baggage: &[&str],
+	attribute_length: Option<usize>,
+) -> impl Layer<S> {
+	tracing_opentelemetry::layer()
+		.with_tracer(tracer)
+		.with_exception_field_propagation(true)
+		.with_threads(true)
+		.with_location(true)
+		.with_tracked_inactivity(true)
+		.with_filter(filter::filter_fn(|metadata| metadata.is_span()))
+		.and_then(RemoteParentLayer)
+		.and_then(BaggageLayer::new(baggage))
+		.and_then(attribute_length.map(TruncationLayer::new))
+}
+
+/// Duration and error metrics for spans, if enabled with the fields allowed as labels
+pub fn metrics<S: Sub>(labels: Option<&[&str]>) -> Option<impl Layer<S>> {
+	labels.map(SpanMetricsLayer::new)
+}
+
+pub fn stop() {
+	opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use opentelemetry::trace::{Span as _, Tracer as _};
+	use std::fs;
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn every_exporter_gets_each_span_once() {
+		let root =
+			std::env::temp_dir().join(format!("instrument-exporters-{}", std::process::id()));
+		fs::create_dir_all(&root).unwrap();
+		let paths: Vec<String> = ["a.json", "b.json"]
+			.iter()
+			.map(|file| format!("file://{}", root.join(file).display()))
+			.collect();
+		let exporters: Vec<Exporter> = paths
+			.iter()
+			.zip(["a", "b"])
+			.map(|(endpoint, name)| Exporter {
+				name,
+				endpoint,
+				..Exporter::LOCAL
+			})
+			.collect();
+
+		for retention in [None, Some(&Retention::default())] {
+			let provider = provider(&exporters, retention, sdktrace::config());
+			provider.tracer("test").start("span").end();
+			for result in provider.force_flush() {
+				result.unwrap();
+			}
+
+			for file in ["a.json", "b.json"] {
+				let lines = fs::read_to_string(root.join(file)).unwrap();
+				let lines: Vec<&str> = lines.lines().collect();
+				assert_eq!(lines.len(), 1, "{} got {:?}", file, lines);
+				fs::remove_file(root.join(file)).unwrap();
+			}
+		}
+
+		fs::remove_dir_all(root).unwrap();
+	}
+
+	#[test]
+	fn retention_keeps_the_configured_sampler() {
+		let config = sdktrace::config().with_sampler(sdktrace::Sampler::AlwaysOff);
+		let provider = provider(&[], Some(&Retention::default()), config);
+		let span = provider.tracer("test").start("span");
+
+		assert!(
+			span.is_recording(),
+			"dropped spans are recorded for retention"
+		);
+		assert!(!span.span_context().is_sampled());
+	}
+}
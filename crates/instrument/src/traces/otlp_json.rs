@@ -0,0 +1,511 @@
+//! Spans in the OTLP-JSON encoding, one `ExportTraceServiceRequest` per line, shared with the
+//! tools reading the files we write
+
+use opentelemetry::sdk::export::trace::SpanData;
+use opentelemetry::sdk::trace::{EvictedHashMap, EvictedQueue};
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::{
+	Event, Link, SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceId,
+};
+use opentelemetry::{Array, InstrumentationLibrary, Key, KeyValue, Value};
+use serde_json::{json, Value as Json};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Spans keyed by the name and version of their instrumentation scope
+type Scopes<'a> = BTreeMap<(&'a str, &'a str), Vec<Json>>;
+
+/// Encodes `batch` as an OTLP `ExportTraceServiceRequest` following the protobuf JSON mapping
+pub fn encode(batch: &[SpanData]) -> Json {
+	let mut resources: Vec<(&Resource, Scopes)> = Vec::new();
+
+	for span in batch {
+		let resource = span.resource.as_ref();
+		let index = match resources.iter().position(|(known, _)| *known == resource) {
+			Some(index) => index,
+			None => {
+				resources.push((resource, BTreeMap::new()));
+				resources.len() - 1
+			}
+		};
+
+		let library = &span.instrumentation_lib;
+		let scope = (
+			library.name.as_ref(),
+			library.version.as_deref().unwrap_or_default(),
+		);
+
+		resources[index]
+			.1
+			.entry(scope)
+			.or_default()
+			.push(encode_span(span));
+	}
+
+	let resource_spans: Vec<Json> = resources
+		.into_iter()
+		.map(|(resource, scopes)| {
+			let scope_spans: Vec<Json> = scopes
+				.into_iter()
+				.map(|((name, version), spans)| {
+					json!({
+						"scope": {"name": name, "version": version},
+						"spans": spans,
+					})
+				})
+				.collect();
+
+			json!({
+				"resource": {"attributes": attributes(resource.iter())},
+				"scopeSpans": scope_spans,
+			})
+		})
+		.collect();
+
+	json!({ "resourceSpans": resource_spans })
+}
+
+fn encode_span(span: &SpanData) -> Json {
+	let context = &span.span_context;
+	let parent_span_id = if span.parent_span_id == opentelemetry::trace::SpanId::INVALID {
+		String::new()
+	} else {
+		span.parent_span_id.to_string()
+	};
+
+	let (code, message) = match &span.status {
+		Status::Unset => (0, ""),
+		Status::Ok => (1, ""),
+		Status::Error { description } => (2, description.as_ref()),
+	};
+
+	let events: Vec<Json> = span
+		.events
+		.iter()
+		.map(|event| {
+			json!({
+				"timeUnixNano": nanos(event.timestamp),
+				"name": event.name,
+				"attributes": attributes(event.attributes.iter().map(|kv| (&kv.key, &kv.value))),
+				"droppedAttributesCount": event.dropped_attributes_count,
+			})
+		})
+		.collect();
+
+	let links: Vec<Json> = span
+		.links
+		.iter()
+		.map(|link| {
+			json!({
+				"traceId": link.span_context.trace_id().to_string(),
+				"spanId": link.span_context.span_id().to_string(),
+				"traceState": link.span_context.trace_state().header(),
+				"attributes": attributes(link.attributes.iter().map(|kv| (&kv.key, &kv.value))),
+				"droppedAttributesCount": link.dropped_attributes_count,
+			})
+		})
+		.collect();
+
+	json!({
+		"traceId": context.trace_id().to_string(),
+		"spanId": context.span_id().to_string(),
+		"traceState": context.trace_state().header(),
+		"parentSpanId": parent_span_id,
+		"name": span.name,
+		"kind": kind(&span.span_kind),
+		"startTimeUnixNano": nanos(span.start_time),
+		"endTimeUnixNano": nanos(span.end_time),
+		"attributes": attributes(span.attributes.iter()),
+		"droppedAttributesCount": span.attributes.dropped_count(),
+		"events": events,
+		"droppedEventsCount": span.events.dropped_count(),
+		"links": links,
+		"droppedLinksCount": span.links.dropped_count(),
+		"status": {"code": code, "message": message},
+	})
+}
+
+fn kind(kind: &SpanKind) -> u8 {
+	match kind {
+		SpanKind::Internal => 1,
+		SpanKind::Server => 2,
+		SpanKind::Client => 3,
+		SpanKind::Producer => 4,
+		SpanKind::Consumer => 5,
+	}
+}
+
+// 64 bit integers are strings in the JSON mapping
+fn nanos(time: SystemTime) -> String {
+	time.duration_since(UNIX_EPOCH)
+		.unwrap_or_default()
+		.as_nanos()
+		.to_string()
+}
+
+fn attributes<'a>(pairs: impl Iterator<Item = (&'a Key, &'a Value)>) -> Vec<Json> {
+	let mut attributes: Vec<Json> = pairs
+		.map(|(key, value)| json!({"key": key.as_str(), "value": any_value(value)}))
+		.collect();
+
+	// attribute maps are unordered, keep the output stable
+	attributes.sort_by(|a, b| a["key"].as_str().cmp(&b["key"].as_str()));
+
+	attributes
+}
+
+fn any_value(value: &Value) -> Json {
+	match value {
+		Value::Bool(value) => json!({ "boolValue": value }),
+		Value::I64(value) => json!({ "intValue": value.to_string() }),
+		Value::F64(value) => json!({ "doubleValue": value }),
+		Value::String(value) => json!({ "stringValue": value.as_str() }),
+		Value::Array(array) => {
+			let values: Vec<Json> = match array {
+				Array::Bool(values) => values.iter().map(|v| any_value(&Value::Bool(*v))).collect(),
+				Array::I64(values) => values.iter().map(|v| any_value(&Value::I64(*v))).collect(),
+				Array::F64(values) => values.iter().map(|v| any_value(&Value::F64(*v))).collect(),
+				Array::String(values) => values
+					.iter()
+					.map(|v| json!({ "stringValue": v.as_str() }))
+					.collect(),
+			};
+
+			json!({ "arrayValue": { "values": values } })
+		}
+	}
+}
+
+/// Decodes spans encoded by [`encode`] or any other OTLP-JSON writer, dropped counts aside since
+/// the SDK can't carry them over
+///
+/// Both the current `scope*` and the older `instrumentationLibrary*` keys are understood
+pub fn decode(json: &Json) -> Vec<SpanData> {
+	let mut batch = Vec::new();
+
+	for resource_spans in list(&json["resourceSpans"]) {
+		let resource = Resource::new(decode_attributes(&resource_spans["resource"]["attributes"]));
+
+		for scope_spans in list(either(
+			resource_spans,
+			"scopeSpans",
+			"instrumentationLibrarySpans",
+		)) {
+			let scope = either(scope_spans, "scope", "instrumentationLibrary");
+			let version = scope["version"]
+				.as_str()
+				.filter(|version| !version.is_empty());
+			let library = InstrumentationLibrary::new(
+				string(&scope["name"]),
+				version.map(String::from),
+				None,
+			);
+
+			for span in list(&scope_spans["spans"]) {
+				batch.push(decode_span(span, &resource, &library));
+			}
+		}
+	}
+
+	batch
+}
+
+fn decode_span(json: &Json, resource: &Resource, library: &InstrumentationLibrary) -> SpanData {
+	let attributes = decode_attributes(&json["attributes"]);
+	let mut span_attributes = EvictedHashMap::new(attributes.len() as u32, attributes.len());
+	for attribute in attributes {
+		span_attributes.insert(attribute);
+	}
+
+	let mut events: Vec<Event> = list(&json["events"])
+		.iter()
+		.map(|event| {
+			Event::new(
+				string(&event["name"]),
+				time(&event["timeUnixNano"]),
+				decode_attributes(&event["attributes"]),
+				0,
+			)
+		})
+		.collect();
+	let mut span_events = EvictedQueue::new(events.len() as u32);
+	span_events.append_vec(&mut events);
+
+	let mut links: Vec<Link> = list(&json["links"])
+		.iter()
+		.map(|link| {
+			Link::new(
+				span_context(link, TraceFlags::SAMPLED),
+				decode_attributes(&link["attributes"]),
+			)
+		})
+		.collect();
+	let mut span_links = EvictedQueue::new(links.len() as u32);
+	span_links.append_vec(&mut links);
+
+	let status = match decode_number(&json["status"]["code"]) {
+		1 => Status::Ok,
+		2 => Status::error(string(&json["status"]["message"])),
+		_ => Status::Unset,
+	};
+
+	let span_kind = match decode_number(&json["kind"]) {
+		2 => SpanKind::Server,
+		3 => SpanKind::Client,
+		4 => SpanKind::Producer,
+		5 => SpanKind::Consumer,
+		_ => SpanKind::Internal,
+	};
+
+	SpanData {
+		span_context: span_context(json, TraceFlags::SAMPLED),
+		parent_span_id: json["parentSpanId"]
+			.as_str()
+			.and_then(|id| SpanId::from_hex(id).ok())
+			.unwrap_or(SpanId::INVALID),
+		span_kind,
+		name: Cow::Owned(string(&json["name"])),
+		start_time: time(&json["startTimeUnixNano"]),
+		end_time: time(&json["endTimeUnixNano"]),
+		attributes: span_attributes,
+		events: span_events,
+		links: span_links,
+		status,
+		resource: Cow::Owned(resource.clone()),
+		instrumentation_lib: library.clone(),
+	}
+}
+
+fn span_context(json: &Json, flags: TraceFlags) -> SpanContext {
+	let trace_id = json["traceId"]
+		.as_str()
+		.and_then(|id| TraceId::from_hex(id).ok());
+	let span_id = json["spanId"]
+		.as_str()
+		.and_then(|id| SpanId::from_hex(id).ok());
+	let trace_state = json["traceState"]
+		.as_str()
+		.and_then(|state| state.parse().ok())
+		.unwrap_or_default();
+
+	SpanContext::new(
+		trace_id.unwrap_or(TraceId::INVALID),
+		span_id.unwrap_or(SpanId::INVALID),
+		flags,
+		false,
+		trace_state,
+	)
+}
+
+/// Decodes a list of `{key, value}` pairs, skipping values the SDK has no type for
+pub fn decode_attributes(json: &Json) -> Vec<KeyValue> {
+	list(json)
+		.iter()
+		.filter_map(|attribute| {
+			let value = decode_value(&attribute["value"])?;
+
+			Some(KeyValue::new(string(&attribute["key"]), value))
+		})
+		.collect()
+}
+
+/// Decodes an `AnyValue`, `kvlistValue` and `bytesValue` have no SDK type and are dropped
+pub fn decode_value(json: &Json) -> Option<Value> {
+	if let Some(value) = json.get("stringValue") {
+		Some(Value::from(string(value)))
+	} else if let Some(value) = json.get("boolValue") {
+		value.as_bool().map(Value::Bool)
+	} else if let Some(value) = json.get("intValue") {
+		match value {
+			Json::String(value) => value.parse().ok().map(Value::I64),
+			value => value.as_i64().map(Value::I64),
+		}
+	} else if let Some(value) = json.get("doubleValue") {
+		value.as_f64().map(Value::F64)
+	} else if let Some(value) = json.get("arrayValue") {
+		let values: Vec<Value> = list(&value["values"])
+			.iter()
+			.filter_map(decode_value)
+			.collect();
+
+		let array = match values.first() {
+			Some(Value::Bool(_)) => Array::Bool(
+				values
+					.iter()
+					.filter_map(|v| match v {
+						Value::Bool(v) => Some(*v),
+						_ => None,
+					})
+					.collect(),
+			),
+			Some(Value::I64(_)) => Array::I64(
+				values
+					.iter()
+					.filter_map(|v| match v {
+						Value::I64(v) => Some(*v),
+						_ => None,
+					})
+					.collect(),
+			),
+			Some(Value::F64(_)) => Array::F64(
+				values
+					.iter()
+					.filter_map(|v| match v {
+						Value::F64(v) => Some(*v),
+						_ => None,
+					})
+					.collect(),
+			),
+			_ => Array::String(
+				values
+					.iter()
+					.filter_map(|v| match v {
+						Value::String(v) => Some(v.clone()),
+						_ => None,
+					})
+					.collect(),
+			),
+		};
+
+		Some(Value::Array(array))
+	} else {
+		None
+	}
+}
+
+fn time(json: &Json) -> SystemTime {
+	UNIX_EPOCH + Duration::from_nanos(decode_number(json))
+}
+
+/// Decodes an unsigned 64 bit integer, which are strings in the JSON mapping though plain numbers
+/// are accepted as well. Anything else is 0, the protobuf default
+pub fn decode_number(json: &Json) -> u64 {
+	match json {
+		Json::String(value) => value.parse().unwrap_or_default(),
+		value => value.as_u64().unwrap_or_default(),
+	}
+}
+
+fn either<'a>(json: &'a Json, current: &str, legacy: &str) -> &'a Json {
+	json.get(current).unwrap_or(&json[legacy])
+}
+
+fn list(json: &Json) -> &[Json] {
+	json.as_array().map(Vec::as_slice).unwrap_or_default()
+}
+
+fn string(json: &Json) -> String {
+	json.as_str().unwrap_or_default().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::Collect;
+	use opentelemetry::sdk::trace::{config, TracerProvider};
+	use opentelemetry::trace::{
+		Span as _, TraceContextExt, TraceState, Tracer, TracerProvider as _,
+	};
+	use opentelemetry::Context;
+
+	#[test]
+	fn decode_reverses_encode() {
+		let collect = Collect::default();
+		let resource = Resource::new([KeyValue::new("service.name", "test")]);
+		let provider = TracerProvider::builder()
+			.with_span_processor(collect.clone())
+			.with_config(config().with_resource(resource))
+			.build();
+		let tracer = provider.versioned_tracer("test", Some("1.0"), None);
+
+		let linked = SpanContext::new(
+			TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+			SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+			TraceFlags::SAMPLED,
+			false,
+			TraceState::default(),
+		);
+		let mut root = tracer
+			.span_builder("root")
+			.with_kind(SpanKind::Server)
+			.with_attributes(vec![
+				KeyValue::new("text", "a"),
+				KeyValue::new("flag", true),
+				KeyValue::new("count", 3),
+				KeyValue::new("ratio", 0.5),
+				KeyValue::new("list", Value::Array(vec![1_i64, 2].into())),
+			])
+			.with_links(vec![Link::new(
+				linked.clone(),
+				vec![KeyValue::new("why", "retry")],
+			)])
+			.start(&tracer);
+		root.add_event("event", vec![KeyValue::new("attempt", 1)]);
+		root.set_status(Status::error("boom"));
+
+		let cx = Context::current_with_span(root);
+		tracer.start_with_context("child", &cx).end();
+		cx.span().end();
+
+		let spans = collect.spans();
+		let encoded = encode(&spans);
+		let decoded = decode(&encoded);
+
+		assert_eq!(encode(&decoded), encoded);
+		assert_eq!(decoded.len(), 2);
+		for (span, decoded) in spans.iter().zip(&decoded) {
+			assert_eq!(decoded.span_context, span.span_context);
+			assert_eq!(decoded.parent_span_id, span.parent_span_id);
+			assert_eq!(decoded.span_kind, span.span_kind);
+			assert_eq!(decoded.name, span.name);
+			assert_eq!(decoded.start_time, span.start_time);
+			assert_eq!(decoded.end_time, span.end_time);
+			assert_eq!(decoded.status, span.status);
+			assert_eq!(decoded.resource, span.resource);
+			assert_eq!(decoded.instrumentation_lib, span.instrumentation_lib);
+		}
+
+		let root = &decoded[1];
+		assert_eq!(
+			root.attributes.get(&Key::new("list")),
+			Some(&Value::Array(vec![1_i64, 2].into()))
+		);
+		assert_eq!(root.events.iter().next().unwrap().name, "event");
+		let link = root.links.iter().next().unwrap();
+		assert_eq!(link.span_context, linked);
+		assert_eq!(link.attributes, [KeyValue::new("why", "retry")]);
+	}
+
+	#[test]
+	fn reads_legacy_keys_and_plain_numbers() {
+		let json = json!({"resourceSpans": [{
+			"instrumentationLibrarySpans": [{
+				"instrumentationLibrary": {"name": "legacy"},
+				"spans": [{
+					"traceId": "4bf92f3577b34da6a3ce929d0e0e4736",
+					"spanId": "00f067aa0ba902b7",
+					"name": "GET /hello",
+					"kind": "2",
+					"startTimeUnixNano": 1_000,
+					"attributes": [
+						{"key": "count", "value": {"intValue": 43}},
+						{"key": "map", "value": {"kvlistValue": {"values": []}}},
+					],
+				}],
+			}],
+		}]});
+
+		let spans = decode(&json);
+		assert_eq!(spans.len(), 1);
+		let span = &spans[0];
+		assert_eq!(span.instrumentation_lib.name, "legacy");
+		assert_eq!(span.span_kind, SpanKind::Server);
+		assert_eq!(span.start_time, UNIX_EPOCH + Duration::from_nanos(1_000));
+		assert_eq!(
+			span.attributes.get(&Key::new("count")),
+			Some(&Value::I64(43))
+		);
+		assert_eq!(span.attributes.len(), 1, "kvlists have no SDK type");
+	}
+}
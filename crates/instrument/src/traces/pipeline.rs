@@ -0,0 +1,442 @@
+use super::buffer::{Buffer, Buffered};
+use super::file::FileExporter;
+use super::stdout::StdoutExporter;
+use futures::future::BoxFuture;
+use http::{Request, Response, StatusCode};
+use opentelemetry::sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry::sdk::trace::{self as sdktrace, BatchConfig, BatchSpanProcessor, Span};
+use opentelemetry::trace::TraceResult;
+use opentelemetry::Context;
+use opentelemetry_http::{Bytes, HttpClient, HttpError};
+use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tonic::metadata::{MetadataKey, MetadataMap};
+
+/// Destination of spans, each with its own queue so a slow one can't hold back the others
+#[derive(Clone, Debug)]
+pub struct Exporter<'a> {
+	/// Label of the exporter's metrics and folder of its buffer, kept apart from the endpoint as
+	/// that may carry credentials
+	pub name: &'a str,
+	/// OTLP endpoint, `stdout` or `file://<path>`
+	pub endpoint: &'a str,
+	/// Protocol spoken to OTLP endpoints
+	pub transport: Transport,
+	/// Sent along with every export to OTLP endpoints, e.g. for authentication
+	pub headers: &'a [(&'a str, &'a str)],
+	pub batch: Batch,
+	/// Keeps failed batches on disk until the endpoint is back, instead of dropping them
+	pub buffer: Option<Buffer<'a>>,
+}
+
+impl Exporter<'static> {
+	/// gRPC exporter to a collector running next to the service
+	pub const LOCAL: Exporter<'static> = Exporter {
+		name: "local",
+		endpoint: "http://localhost:4317",
+		transport: Transport::Grpc,
+		headers: &[],
+		batch: Batch::DEFAULT,
+		buffer: None,
+	};
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Transport {
+	#[default]
+	Grpc,
+	/// Protobuf over HTTP to the collector's base URL, which gets `/v1/traces` appended
+	HttpProtobuf,
+}
+
+/// Settings of the batch span processor, defaults follow the `OTEL_BSP_*` spec
+#[derive(Clone, Debug)]
+pub struct Batch {
+	/// Spans waiting for export, any above it are dropped
+	pub max_queue_size: usize,
+	/// Spans sent on each export, capped by `max_queue_size`
+	pub max_export_batch_size: usize,
+	/// Delay between exports when batches don't fill up
+	pub scheduled_delay: Duration,
+	/// Time an export may take before it's cancelled
+	pub max_export_timeout: Duration,
+}
+
+impl Batch {
+	pub const DEFAULT: Batch = Batch {
+		max_queue_size: 2048,
+		max_export_batch_size: 512,
+		scheduled_delay: Duration::from_millis(5000),
+		max_export_timeout: Duration::from_millis(30000),
+	};
+}
+
+impl Default for Batch {
+	fn default() -> Self {
+		Batch::DEFAULT
+	}
+}
+
+/// Batch span processor sending to `exporter`
+pub fn processor(exporter: &Exporter) -> MonitoredProcessor {
+	match exporter.endpoint {
+		"stdout" => monitored(StdoutExporter::default(), exporter),
+		endpoint => match endpoint.strip_prefix("file://") {
+			Some(path) => monitored(FileExporter::new(Path::new(path)), exporter),
+			None => monitored(otlp(exporter), exporter),
+		},
+	}
+}
+
+pub(super) fn otlp(exporter: &Exporter) -> opentelemetry_otlp::SpanExporter {
+	let builder: SpanExporterBuilder = match exporter.transport {
+		Transport::Grpc => {
+			let mut metadata = MetadataMap::with_capacity(exporter.headers.len());
+			for (key, value) in exporter.headers {
+				let key =
+					MetadataKey::from_bytes(key.as_bytes()).expect("Invalid OTLP header name");
+				let value = value.parse().expect("Invalid OTLP header value");
+				metadata.insert(key, value);
+			}
+
+			opentelemetry_otlp::new_exporter()
+				.tonic()
+				.with_endpoint(exporter.endpoint)
+				.with_metadata(metadata)
+				.into()
+		}
+		Transport::HttpProtobuf => {
+			let headers: HashMap<String, String> = exporter
+				.headers
+				.iter()
+				.map(|(key, value)| (key.to_string(), value.to_string()))
+				.collect();
+
+			let endpoint = format!("{}/v1/traces", exporter.endpoint.trim_end_matches('/'));
+
+			opentelemetry_otlp::new_exporter()
+				.http()
+				.with_endpoint(endpoint)
+				.with_headers(headers)
+				.with_http_client(StatusClient::default())
+				.into()
+		}
+	};
+
+	builder
+		.build_span_exporter()
+		.expect("Unable to create OTLP exporter")
+}
+
+/// Status of a response the collector refused the export with
+#[derive(Debug)]
+pub(super) struct HttpStatus(pub StatusCode);
+
+impl fmt::Display for HttpStatus {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "collector responded with {}", self.0)
+	}
+}
+
+impl Error for HttpStatus {}
+
+/// HTTP client failing the exports that aren't answered with a success, which the OTLP exporter
+/// would take as sent
+#[derive(Debug, Default)]
+struct StatusClient(reqwest::Client);
+
+#[axum::async_trait]
+impl HttpClient for StatusClient {
+	async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Bytes>, HttpError> {
+		let response = self.0.send(request).await?;
+
+		if response.status().is_success() {
+			Ok(response)
+		} else {
+			Err(Box::new(HttpStatus(response.status())))
+		}
+	}
+}
+
+fn monitored<E: SpanExporter + 'static>(inner: E, exporter: &Exporter) -> MonitoredProcessor {
+	let name = exporter.name.to_string();
+	let monitored = MonitoredExporter {
+		inner,
+		name: name.clone(),
+	};
+
+	match &exporter.buffer {
+		Some(buffer) => queue(
+			Buffered::new(monitored, buffer, &name),
+			name,
+			&exporter.batch,
+		),
+		None => queue(monitored, name, &exporter.batch),
+	}
+}
+
+// Room in the wrapped processor's queue on top of the spans, for the flush and shutdown messages
+// sharing it. Spans it dropped on a full queue would keep their slots in ours forever
+const CONTROL_MESSAGES: usize = 4;
+
+/// Batch span processor reporting its own health, labeled by `name`
+///
+/// The queue bound is enforced here rather than in the wrapped processor, which drops spans
+/// without telling anyone
+fn queue<E: SpanExporter + 'static>(
+	exporter: E,
+	name: String,
+	batch: &Batch,
+) -> MonitoredProcessor {
+	let queued = Arc::new(AtomicUsize::new(0));
+
+	let exporter = Dequeued {
+		inner: exporter,
+		queued: queued.clone(),
+	};
+
+	let config = BatchConfig::default()
+		.with_max_queue_size(batch.max_queue_size + CONTROL_MESSAGES)
+		.with_max_export_batch_size(batch.max_export_batch_size.min(batch.max_queue_size))
+		.with_scheduled_delay(batch.scheduled_delay)
+		.with_max_export_timeout(batch.max_export_timeout);
+
+	let inner = BatchSpanProcessor::builder(exporter, opentelemetry::runtime::Tokio)
+		.with_batch_config(config)
+		.build();
+
+	MonitoredProcessor {
+		inner,
+		name,
+		queued,
+		capacity: batch.max_queue_size,
+	}
+}
+
+#[derive(Debug)]
+pub struct MonitoredProcessor {
+	inner: BatchSpanProcessor<opentelemetry::runtime::Tokio>,
+	name: String,
+	// spans handed to the processor and not yet picked up for export
+	queued: Arc<AtomicUsize>,
+	capacity: usize,
+}
+
+impl sdktrace::SpanProcessor for MonitoredProcessor {
+	fn on_start(&self, span: &mut Span, cx: &Context) {
+		self.inner.on_start(span, cx)
+	}
+
+	fn on_end(&self, span: SpanData) {
+		if !span.span_context.is_sampled() {
+			return;
+		}
+
+		let reserved = self
+			.queued
+			.fetch_update(Ordering::AcqRel, Ordering::Acquire, |queued| {
+				(queued < self.capacity).then_some(queued + 1)
+			});
+
+		match reserved {
+			Ok(_) => self.inner.on_end(span),
+			Err(_) => metrics::counter!("spans_dropped_total", 1, "exporter" => self.name.clone()),
+		}
+	}
+
+	fn force_flush(&self) -> TraceResult<()> {
+		self.inner.force_flush()
+	}
+
+	fn shutdown(&mut self) -> TraceResult<()> {
+		self.inner.shutdown()
+	}
+}
+
+// Releases the queue slots of spans once the processor hands them over for export
+#[derive(Debug)]
+struct Dequeued<E> {
+	inner: E,
+	queued: Arc<AtomicUsize>,
+}
+
+impl<E: SpanExporter> SpanExporter for Dequeued<E> {
+	fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+		self.queued.fetch_sub(batch.len(), Ordering::AcqRel);
+		self.inner.export(batch)
+	}
+
+	fn shutdown(&mut self) {
+		self.inner.shutdown()
+	}
+
+	fn force_flush(&mut self) -> BoxFuture<'static, ExportResult> {
+		self.inner.force_flush()
+	}
+}
+
+#[derive(Debug)]
+struct MonitoredExporter<E> {
+	inner: E,
+	name: String,
+}
+
+impl<E: SpanExporter> SpanExporter for MonitoredExporter<E> {
+	fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+		let size = batch.len();
+
+		let attempt = Attempt {
+			name: self.name.clone(),
+			size,
+			started: Instant::now(),
+			succeeded: None,
+		};
+		let export = self.inner.export(batch);
+
+		Box::pin(async move {
+			// rebind so the whole attempt moves in, instead of just the field assigned below
+			let mut attempt = attempt;
+			let result = export.await;
+			attempt.succeeded = Some(result.is_ok());
+
+			result
+		})
+	}
+
+	fn shutdown(&mut self) {
+		self.inner.shutdown()
+	}
+
+	fn force_flush(&mut self) -> BoxFuture<'static, ExportResult> {
+		self.inner.force_flush()
+	}
+}
+
+// Records on drop since the processor cancels exports that time out
+struct Attempt {
+	name: String,
+	size: usize,
+	started: Instant,
+	succeeded: Option<bool>,
+}
+
+impl Drop for Attempt {
+	fn drop(&mut self) {
+		let labels = [("exporter", self.name.clone())];
+		let duration = self.started.elapsed().as_secs_f64();
+		metrics::histogram!("export_duration_seconds", duration, &labels);
+
+		if self.succeeded == Some(true) {
+			metrics::counter!("spans_exported_total", self.size as u64, &labels);
+		} else {
+			metrics::counter!("export_errors_total", 1, &labels);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::{batch, record_metrics, Exported, Metrics};
+	use futures::executor::block_on;
+	use opentelemetry::sdk::trace::SpanProcessor as _;
+
+	#[tokio::test]
+	async fn builds_every_kind_of_exporter() {
+		let headers = [("authorization", "Bearer secret")];
+		let file = std::env::temp_dir().join(format!("instrument-pipeline-{}", std::process::id()));
+		let file = format!("file://{}", file.display());
+
+		for (endpoint, transport) in [
+			("http://localhost:4317", Transport::Grpc),
+			("http://localhost:4318/", Transport::HttpProtobuf),
+			("stdout", Transport::Grpc),
+			(file.as_str(), Transport::Grpc),
+		] {
+			let exporter = Exporter {
+				name: "test",
+				endpoint,
+				transport,
+				headers: &headers,
+				..Exporter::LOCAL
+			};
+
+			processor(&exporter);
+		}
+	}
+
+	#[tokio::test]
+	#[should_panic(expected = "Invalid OTLP header name")]
+	async fn rejects_invalid_grpc_headers() {
+		processor(&Exporter {
+			headers: &[("not a header", "value")],
+			..Exporter::LOCAL
+		});
+	}
+
+	#[test]
+	fn attempts_are_recorded_once_settled_or_cancelled() {
+		record_metrics();
+		let labels = [("exporter", "test")];
+		let mut exporter = MonitoredExporter {
+			inner: Exported::default(),
+			name: String::from("test"),
+		};
+
+		block_on(exporter.export(batch("span"))).unwrap();
+		exporter.inner.fail = true;
+		block_on(exporter.export(batch("span"))).unwrap_err();
+		drop(exporter.export(batch("span")));
+
+		let metrics = Metrics::snapshot();
+		assert_eq!(metrics.counter("spans_exported_total", &labels), 1);
+		assert_eq!(
+			metrics.counter("export_errors_total", &labels),
+			2,
+			"cancelled exports are errors"
+		);
+		assert_eq!(
+			metrics.histogram("export_duration_seconds", &labels).len(),
+			3
+		);
+	}
+
+	#[tokio::test]
+	async fn drops_spans_above_the_queue_size() {
+		record_metrics();
+		let labels = [("exporter", "test")];
+		let exported = Exported::default();
+		let batch_config = Batch {
+			max_queue_size: 2,
+			max_export_batch_size: 2,
+			scheduled_delay: Duration::from_secs(3600),
+			..Batch::DEFAULT
+		};
+		let processor = queue(exported.clone(), String::from("test"), &batch_config);
+
+		// The worker doesn't get to run until we yield, so nothing leaves the queue
+		for name in ["span-1", "span-2", "span-3"] {
+			processor.on_end(batch(name).remove(0));
+		}
+		assert_eq!(
+			Metrics::snapshot().counter("spans_dropped_total", &labels),
+			1
+		);
+
+		while exported.spans().len() < 2 {
+			tokio::task::yield_now().await;
+		}
+		processor.on_end(batch("span-4").remove(0));
+		assert_eq!(
+			Metrics::snapshot().counter("spans_dropped_total", &labels),
+			1,
+			"exported spans free their slots"
+		);
+	}
+}
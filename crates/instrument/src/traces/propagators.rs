@@ -0,0 +1,184 @@
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::sdk::propagation::{
+	BaggagePropagator, TextMapCompositePropagator, TraceContextPropagator,
+};
+use opentelemetry_zipkin::B3Encoding;
+use std::str::FromStr;
+
+/// Formats of trace context accepted and sent on requests, named as in `OTEL_PROPAGATORS`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Propagator {
+	TraceContext,
+	Baggage,
+	B3,
+	B3Multi,
+	Jaeger,
+}
+
+impl FromStr for Propagator {
+	type Err = String;
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value.trim() {
+			"tracecontext" => Ok(Propagator::TraceContext),
+			"baggage" => Ok(Propagator::Baggage),
+			"b3" => Ok(Propagator::B3),
+			"b3multi" => Ok(Propagator::B3Multi),
+			"jaeger" => Ok(Propagator::Jaeger),
+			other => Err(format!("unknown propagator: {}", other)),
+		}
+	}
+}
+
+impl Propagator {
+	/// Reads an `OTEL_PROPAGATORS` list, returning the names it doesn't support apart
+	///
+	/// Empty entries and `none` add nothing, so `none` alone disables propagation
+	pub fn list(value: &str) -> (Vec<Propagator>, Vec<String>) {
+		let mut propagators = Vec::new();
+		let mut unknown = Vec::new();
+
+		for name in value.split(',').map(str::trim) {
+			match name {
+				"" | "none" => {}
+				name => match name.parse() {
+					Ok(propagator) => propagators.push(propagator),
+					Err(_) => unknown.push(name.to_string()),
+				},
+			}
+		}
+
+		(propagators, unknown)
+	}
+
+	fn build(&self) -> Box<dyn TextMapPropagator + Send + Sync> {
+		match self {
+			Propagator::TraceContext => Box::new(TraceContextPropagator::new()),
+			Propagator::Baggage => Box::new(BaggagePropagator::new()),
+			Propagator::B3 => Box::new(opentelemetry_zipkin::Propagator::with_encoding(
+				B3Encoding::SingleHeader,
+			)),
+			Propagator::B3Multi => Box::new(opentelemetry_zipkin::Propagator::with_encoding(
+				B3Encoding::MultipleHeader,
+			)),
+			Propagator::Jaeger => Box::new(opentelemetry_jaeger::Propagator::new()),
+		}
+	}
+}
+
+/// Combines the propagators in order, later ones take precedence when extracting
+pub fn composite(propagators: &[Propagator]) -> TextMapCompositePropagator {
+	TextMapCompositePropagator::new(propagators.iter().map(Propagator::build).collect())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use opentelemetry::trace::TraceState;
+	use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId};
+	use opentelemetry::Context;
+	use std::collections::HashMap;
+
+	const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+	const SPAN_ID: &str = "00f067aa0ba902b7";
+
+	fn extract(propagator: Propagator, headers: &[(&str, &str)]) -> SpanContext {
+		let carrier: HashMap<String, String> = headers
+			.iter()
+			.map(|(key, value)| (key.to_string(), value.to_string()))
+			.collect();
+
+		let context = composite(&[propagator]).extract(&carrier);
+		let span = context.span();
+
+		span.span_context().clone()
+	}
+
+	fn inject(propagator: Propagator, sampled: bool) -> HashMap<String, String> {
+		let flags = if sampled {
+			TraceFlags::SAMPLED
+		} else {
+			TraceFlags::default()
+		};
+		let span_context = SpanContext::new(
+			TraceId::from_hex(TRACE_ID).unwrap(),
+			SpanId::from_hex(SPAN_ID).unwrap(),
+			flags,
+			true,
+			TraceState::default(),
+		);
+
+		let mut carrier = HashMap::new();
+		let context = Context::new().with_remote_span_context(span_context);
+		composite(&[propagator]).inject_context(&context, &mut carrier);
+
+		carrier
+	}
+
+	#[test]
+	fn b3_round_trips_in_both_encodings() {
+		let single = inject(Propagator::B3, true);
+		assert_eq!(single["b3"], format!("{}-{}-1", TRACE_ID, SPAN_ID));
+
+		let multi = inject(Propagator::B3Multi, false);
+		assert_eq!(multi["x-b3-traceid"], TRACE_ID);
+		assert_eq!(multi["x-b3-spanid"], SPAN_ID);
+		assert_eq!(multi["x-b3-sampled"], "0");
+
+		let single: Vec<(&str, &str)> = single.iter().map(|(k, v)| (&**k, &**v)).collect();
+		let extracted = extract(Propagator::B3Multi, &single);
+		assert_eq!(extracted.trace_id().to_string(), TRACE_ID);
+		assert!(extracted.is_sampled());
+
+		let multi: Vec<(&str, &str)> = multi.iter().map(|(k, v)| (&**k, &**v)).collect();
+		let extracted = extract(Propagator::B3, &multi);
+		assert_eq!(extracted.span_id().to_string(), SPAN_ID);
+		assert!(!extracted.is_sampled());
+	}
+
+	#[test]
+	fn b3_pads_short_trace_ids() {
+		let extracted = extract(
+			Propagator::B3,
+			&[("b3", "a3ce929d0e0e4736-00f067aa0ba902b7-1")],
+		);
+
+		assert_eq!(
+			extracted.trace_id().to_string(),
+			"0000000000000000a3ce929d0e0e4736"
+		);
+		assert!(extracted.is_sampled());
+	}
+
+	#[test]
+	fn jaeger_accepts_encoded_headers() {
+		let header = format!("{}%3A{}%3A0%3A3", TRACE_ID, SPAN_ID);
+		let extracted = extract(Propagator::Jaeger, &[("uber-trace-id", &header)]);
+
+		assert_eq!(extracted.trace_id().to_string(), TRACE_ID);
+		assert!(extracted.is_sampled());
+
+		let injected = inject(Propagator::Jaeger, false);
+		assert_eq!(
+			injected["uber-trace-id"],
+			format!("{}:{}:0:0", TRACE_ID, SPAN_ID)
+		);
+	}
+
+	#[test]
+	fn names_follow_otel_propagators() {
+		let (propagators, unknown) = Propagator::list("tracecontext, baggage,,b3multi,xray");
+
+		assert_eq!(
+			propagators,
+			vec![
+				Propagator::TraceContext,
+				Propagator::Baggage,
+				Propagator::B3Multi
+			]
+		);
+		assert_eq!(unknown, ["xray"]);
+		assert_eq!(Propagator::list("none"), (vec![], vec![]));
+		assert_eq!(Propagator::list(""), (vec![], vec![]));
+	}
+}
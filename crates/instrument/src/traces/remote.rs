@@ -0,0 +1,85 @@
+use crate::Sub;
+use opentelemetry::Context as OtelContext;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::span::{Attributes, Id};
+use tracing::Span;
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+/// Parents explicit root spans built by [`with_remote_parent`] on the context given to it
+///
+/// `tracing_opentelemetry` ignores the attached context for spans created with `parent: None`,
+/// which are how we continue a remote trace regardless of the span we're currently in
+pub struct RemoteParentLayer;
+
+/// Marks the attached context as the parent of the next explicit root span, only once so that
+/// contexts derived from that span don't pass it on
+struct RemoteParent(AtomicBool);
+
+/// Builds the span of `make`, which must be created with `parent: None`, as a child of
+/// `remote_context`
+///
+/// The context stays current while the span is created, since its trace ids are resolved right
+/// away and wouldn't follow a later `set_parent`
+pub fn with_remote_parent(remote_context: OtelContext, make: impl FnOnce() -> Span) -> Span {
+	let _guard = remote_context
+		.with_value(RemoteParent(AtomicBool::new(true)))
+		.attach();
+
+	make()
+}
+
+impl<S: Sub> Layer<S> for RemoteParentLayer {
+	fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+		if !attrs.is_root() {
+			return;
+		}
+
+		let current = OtelContext::current();
+		let marked = current
+			.get::<RemoteParent>()
+			.is_some_and(|RemoteParent(unused)| unused.swap(false, Ordering::AcqRel));
+		if !marked {
+			return;
+		}
+
+		let span = ctx.span(id).unwrap();
+		let mut extensions = span.extensions_mut();
+		if let Some(data) = extensions.get_mut::<OtelData>() {
+			data.parent_cx = current;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::propagation::{consumer_span, extract};
+	use crate::testing::{remote_trace_id, traced, TRACEPARENT};
+	use opentelemetry::trace::TraceContextExt;
+	use std::collections::HashMap;
+	use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+	#[test]
+	fn only_marked_spans_get_the_attached_parent() {
+		let carrier = HashMap::from([("traceparent".to_string(), TRACEPARENT.to_string())]);
+		let trace_id = |span: &tracing::Span| span.context().span().span_context().trace_id();
+
+		traced(|| {
+			let _attached = extract(&carrier).attach();
+			let root = tracing::info_span!(parent: None, "root");
+			assert_ne!(trace_id(&root), remote_trace_id());
+
+			let consumer = consumer_span("process", &carrier);
+			assert_eq!(trace_id(&consumer), remote_trace_id());
+
+			let _derived = consumer.context().attach();
+			let root = tracing::info_span!(parent: None, "root");
+			assert_ne!(
+				trace_id(&root),
+				remote_trace_id(),
+				"contexts of marked spans don't carry the mark"
+			);
+		});
+	}
+}
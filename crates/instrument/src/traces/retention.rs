@@ -0,0 +1,372 @@
+use opentelemetry::sdk::export::trace::SpanData;
+use opentelemetry::sdk::trace::{ShouldSample, Span};
+use opentelemetry::sdk::{trace as sdktrace, InstrumentationLibrary};
+use opentelemetry::trace::{
+	Link, OrderMap, SamplingDecision, SamplingResult, SpanContext, SpanKind, Status, TraceFlags,
+	TraceId, TraceResult,
+};
+use opentelemetry::{Context, Key, Value};
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Holds the spans of unsampled traces for a while, exporting the traces where any span failed
+#[derive(Clone, Debug)]
+pub struct Retention {
+	/// Time a trace is held after its first span ends, waiting for one of them to fail
+	pub window: Duration,
+	/// Spans held across all traces, the oldest traces are dropped to stay under it
+	pub max_spans: usize,
+}
+
+impl Default for Retention {
+	fn default() -> Self {
+		Retention {
+			window: Duration::from_secs(10),
+			max_spans: 10_000,
+		}
+	}
+}
+
+/// Records the spans `inner` drops, which stay unsampled so that only [`Retaining`] sees them
+#[derive(Clone, Debug)]
+pub struct RecordDropped(pub Box<dyn ShouldSample>);
+
+impl ShouldSample for RecordDropped {
+	fn should_sample(
+		&self,
+		parent_context: Option<&Context>,
+		trace_id: TraceId,
+		name: &str,
+		span_kind: &SpanKind,
+		attributes: &OrderMap<Key, Value>,
+		links: &[Link],
+		instrumentation_library: &InstrumentationLibrary,
+	) -> SamplingResult {
+		let mut result = self.0.should_sample(
+			parent_context,
+			trace_id,
+			name,
+			span_kind,
+			attributes,
+			links,
+			instrumentation_library,
+		);
+
+		if let SamplingDecision::Drop = result.decision {
+			result.decision = SamplingDecision::RecordOnly;
+		}
+
+		result
+	}
+}
+
+/// Passes sampled spans on to `processors`, and unsampled ones only when their trace failed
+#[derive(Debug)]
+pub struct Retaining<P> {
+	processors: Vec<P>,
+	retention: Retention,
+	state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+	traces: HashMap<TraceId, Held>,
+	// traces by the time their first span ended, which may have been dropped already
+	order: VecDeque<(Instant, TraceId)>,
+	spans: usize,
+}
+
+#[derive(Debug)]
+struct Held {
+	since: Instant,
+	spans: Vec<SpanData>,
+	// Spans of kept traces go through right away
+	kept: bool,
+}
+
+impl<P: sdktrace::SpanProcessor> Retaining<P> {
+	pub fn new(processors: Vec<P>, retention: &Retention) -> Self {
+		Retaining {
+			processors,
+			retention: retention.clone(),
+			state: Mutex::new(State::default()),
+		}
+	}
+
+	fn forward(&self, span: SpanData) {
+		if let Some((last, rest)) = self.processors.split_last() {
+			for processor in rest {
+				processor.on_end(span.clone());
+			}
+			last.on_end(span);
+		}
+	}
+
+	fn hold(&self, span: SpanData) -> Vec<SpanData> {
+		let now = Instant::now();
+		let mut guard = self.state.lock().unwrap();
+		let state = &mut *guard;
+
+		state.expire(now, self.retention.window);
+
+		let trace_id = span.span_context.trace_id();
+		let failed = matches!(span.status, Status::Error { .. });
+
+		let held = state.traces.entry(trace_id).or_insert_with(|| {
+			state.order.push_back((now, trace_id));
+
+			Held {
+				since: now,
+				spans: Vec::new(),
+				kept: false,
+			}
+		});
+		let released = if held.kept {
+			vec![span]
+		} else if failed {
+			held.kept = true;
+			metrics::counter!("retained_traces_kept_total", 1);
+
+			let mut spans = mem::take(&mut held.spans);
+			spans.push(span);
+			spans
+		} else {
+			held.spans.push(span);
+			Vec::new()
+		};
+
+		// Everything released but the span at hand was being held
+		match released.len() {
+			0 => {
+				state.spans += 1;
+				state.evict(self.retention.max_spans);
+			}
+			count => state.spans -= count - 1,
+		}
+
+		metrics::gauge!("retained_spans", state.spans as f64);
+
+		released
+	}
+}
+
+impl State {
+	fn expire(&mut self, now: Instant, window: Duration) {
+		while let Some(&(since, trace_id)) = self.order.front() {
+			if now.duration_since(since) < window {
+				break;
+			}
+
+			self.order.pop_front();
+			self.drop_trace(since, trace_id, "expired");
+		}
+	}
+
+	fn evict(&mut self, max_spans: usize) {
+		while self.spans > max_spans {
+			let Some((since, trace_id)) = self.order.pop_front() else {
+				break;
+			};
+
+			self.drop_trace(since, trace_id, "capacity");
+		}
+	}
+
+	// Skips traces that were dropped before and held again since
+	fn drop_trace(&mut self, since: Instant, trace_id: TraceId, reason: &'static str) {
+		if self.traces.get(&trace_id).map(|held| held.since) != Some(since) {
+			return;
+		}
+
+		if let Some(held) = self.traces.remove(&trace_id) {
+			self.spans -= held.spans.len();
+			if !held.kept {
+				metrics::counter!("retained_traces_dropped_total", 1, "reason" => reason);
+			}
+		}
+	}
+}
+
+// Retained spans get exported as any other, which only happens to sampled ones
+fn sampled(mut span: SpanData) -> SpanData {
+	let context = &span.span_context;
+	span.span_context = SpanContext::new(
+		context.trace_id(),
+		context.span_id(),
+		context.trace_flags() | TraceFlags::SAMPLED,
+		context.is_remote(),
+		context.trace_state().clone(),
+	);
+
+	span
+}
+
+impl<P: sdktrace::SpanProcessor> sdktrace::SpanProcessor for Retaining<P> {
+	fn on_start(&self, span: &mut Span, cx: &Context) {
+		for processor in &self.processors {
+			processor.on_start(span, cx);
+		}
+	}
+
+	fn on_end(&self, span: SpanData) {
+		// Held traces only expire as unsampled spans end, which keeps the lock off sampled ones
+		if span.span_context.is_sampled() {
+			return self.forward(span);
+		}
+
+		for span in self.hold(span) {
+			self.forward(sampled(span));
+		}
+	}
+
+	fn force_flush(&self) -> TraceResult<()> {
+		self.processors
+			.iter()
+			.map(|processor| processor.force_flush())
+			.fold(Ok(()), Result::and)
+	}
+
+	fn shutdown(&mut self) -> TraceResult<()> {
+		let mut state = self.state.lock().unwrap();
+		state.expire(Instant::now(), Duration::ZERO);
+		drop(state);
+
+		self.processors
+			.iter_mut()
+			.map(|processor| processor.shutdown())
+			.fold(Ok(()), Result::and)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::{record_metrics, Collect, Metrics};
+	use opentelemetry::sdk::trace::Sampler;
+	use opentelemetry::trace::{Span as _, TraceContextExt, Tracer, TracerProvider as _};
+	use std::thread;
+
+	// Provider sampling nothing, with every span going through retention into the collected ones
+	fn retaining(retention: Retention) -> (sdktrace::TracerProvider, Collect) {
+		let collect = Collect::default();
+		let sampler = RecordDropped(Box::new(Sampler::AlwaysOff));
+		let provider = sdktrace::TracerProvider::builder()
+			.with_span_processor(Retaining::new(vec![collect.clone()], &retention))
+			.with_config(sdktrace::config().with_sampler(sampler))
+			.build();
+
+		(provider, collect)
+	}
+
+	fn names(collect: &Collect) -> Vec<String> {
+		collect
+			.spans()
+			.iter()
+			.map(|span| span.name.to_string())
+			.collect()
+	}
+
+	#[test]
+	fn exports_unsampled_traces_that_failed() {
+		let (provider, collect) = retaining(Retention {
+			max_spans: 2,
+			..Default::default()
+		});
+		let tracer = provider.tracer("test");
+
+		let trace = |failed: bool| {
+			let root = tracer.start("root");
+			let cx = Context::current_with_span(root);
+			let mut child = tracer.start_with_context("child", &cx);
+			if failed {
+				child.set_status(Status::error("boom"));
+			}
+			child.end();
+			cx.span().end();
+		};
+
+		trace(false);
+		assert!(collect.spans().is_empty());
+
+		trace(true);
+		let exported = collect.spans();
+		let names: Vec<_> = exported.iter().map(|span| span.name.as_ref()).collect();
+		assert_eq!(
+			names,
+			["child", "root"],
+			"the root ends after its trace got kept"
+		);
+		assert!(exported.iter().all(|span| span.span_context.is_sampled()));
+	}
+
+	#[test]
+	fn drops_traces_held_past_the_window() {
+		record_metrics();
+		let (provider, collect) = retaining(Retention {
+			window: Duration::from_millis(20),
+			..Default::default()
+		});
+		let tracer = provider.tracer("test");
+
+		let root = Context::current_with_span(tracer.start("root"));
+		tracer.start_with_context("early", &root).end();
+		thread::sleep(Duration::from_millis(40));
+
+		tracer.start("other").end();
+		let mut late = tracer.start_with_context("late", &root);
+		late.set_status(Status::error("boom"));
+		late.end();
+
+		assert_eq!(names(&collect), ["late"], "the early span expired");
+		let metrics = Metrics::snapshot();
+		assert_eq!(
+			metrics.counter("retained_traces_dropped_total", &[("reason", "expired")]),
+			1
+		);
+		assert_eq!(
+			metrics.counter("retained_traces_dropped_total", &[("reason", "capacity")]),
+			0
+		);
+		assert_eq!(metrics.gauge("retained_spans", &[]), Some(1.0));
+	}
+
+	#[test]
+	fn evicts_the_oldest_traces_above_max_spans() {
+		record_metrics();
+		let (provider, collect) = retaining(Retention {
+			max_spans: 2,
+			..Default::default()
+		});
+		let tracer = provider.tracer("test");
+
+		let first = Context::current_with_span(tracer.start("first"));
+		tracer.start_with_context("first.a", &first).end();
+		tracer.start_with_context("first.b", &first).end();
+		let second = Context::current_with_span(tracer.start("second"));
+		tracer.start_with_context("second.a", &second).end();
+
+		for cx in [&first, &second] {
+			cx.span().set_status(Status::error("boom"));
+			cx.span().end();
+		}
+
+		assert_eq!(
+			names(&collect),
+			["first", "second.a", "second"],
+			"the first trace was evicted for the second one"
+		);
+		let metrics = Metrics::snapshot();
+		assert_eq!(
+			metrics.counter("retained_traces_dropped_total", &[("reason", "capacity")]),
+			1
+		);
+		assert_eq!(
+			metrics.counter("retained_traces_dropped_total", &[("reason", "expired")]),
+			0
+		);
+		assert_eq!(metrics.counter("retained_traces_kept_total", &[]), 2);
+		assert_eq!(metrics.gauge("retained_spans", &[]), Some(0.0));
+	}
+}
@@ -0,0 +1,160 @@
+use crate::Sub;
+use metrics::Label;
+use std::fmt::Debug;
+use std::time::Instant;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+/// Records `span_duration_seconds` and `span_errors_total` for every span when it closes
+///
+/// Metrics are labeled by span name, `otel.name` when set, and the allow-listed fields
+pub struct SpanMetricsLayer {
+	labels: Vec<String>,
+}
+
+impl SpanMetricsLayer {
+	pub fn new(labels: &[&str]) -> Self {
+		SpanMetricsLayer {
+			labels: labels.iter().map(|label| label.to_string()).collect(),
+		}
+	}
+}
+
+struct Timing {
+	name: String,
+	labels: Vec<(String, String)>,
+	error: bool,
+	started: Instant,
+}
+
+struct Recorder<'a> {
+	allowed: &'a [String],
+	timing: &'a mut Timing,
+}
+
+impl<'a> Recorder<'a> {
+	fn record(&mut self, field: &Field, value: String) {
+		match field.name() {
+			"otel.name" => self.timing.name = value,
+			"otel.status_code" => self.timing.error = value == "ERROR",
+			name if self.allowed.iter().any(|allowed| allowed == name) => {
+				let labels = &mut self.timing.labels;
+				labels.retain(|(label, _)| label != name);
+				labels.push((name.to_string(), value));
+			}
+			_ => {}
+		}
+	}
+}
+
+impl<'a> Visit for Recorder<'a> {
+	fn record_str(&mut self, field: &Field, value: &str) {
+		self.record(field, value.to_string());
+	}
+
+	fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+		self.record(field, format!("{:?}", value));
+	}
+}
+
+impl<S: Sub> Layer<S> for SpanMetricsLayer {
+	fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+		let span = ctx.span(id).unwrap();
+
+		let mut timing = Timing {
+			name: attrs.metadata().name().to_string(),
+			labels: Vec::new(),
+			error: false,
+			started: Instant::now(),
+		};
+		attrs.record(&mut Recorder {
+			allowed: &self.labels,
+			timing: &mut timing,
+		});
+
+		span.extensions_mut().insert(timing);
+	}
+
+	fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+		let span = ctx.span(id).unwrap();
+
+		let mut extensions = span.extensions_mut();
+		if let Some(timing) = extensions.get_mut::<Timing>() {
+			values.record(&mut Recorder {
+				allowed: &self.labels,
+				timing,
+			});
+		}
+	}
+
+	fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+		let span = ctx.span(&id).unwrap();
+
+		let mut extensions = span.extensions_mut();
+		let timing = match extensions.remove::<Timing>() {
+			Some(timing) => timing,
+			None => return,
+		};
+
+		let duration = timing.started.elapsed().as_secs_f64();
+		let labels: Vec<Label> = std::iter::once(Label::new("span", timing.name))
+			.chain(
+				timing
+					.labels
+					.into_iter()
+					.map(|(key, value)| Label::new(key, value)),
+			)
+			.collect();
+
+		if timing.error {
+			metrics::counter!("span_errors_total", 1, labels.clone());
+		}
+		metrics::histogram!("span_duration_seconds", duration, labels);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::{record_metrics, Metrics};
+	use tracing::field::Empty;
+	use tracing_subscriber::layer::SubscriberExt;
+
+	#[test]
+	fn labels_by_name_and_allowed_fields() {
+		record_metrics();
+
+		let subscriber =
+			tracing_subscriber::registry().with(SpanMetricsLayer::new(&["http.route"]));
+		tracing::subscriber::with_default(subscriber, || {
+			let span = tracing::info_span!(
+				"HTTP Request",
+				otel.name = %"GET /users",
+				otel.status_code = Empty,
+				http.route = %"/users",
+				user.id = 42,
+			);
+			span.record("otel.status_code", "ERROR");
+			drop(span);
+
+			drop(tracing::info_span!("plain", user.id = 42));
+		});
+
+		let metrics = Metrics::snapshot();
+		let labels = [("span", "GET /users"), ("http.route", "/users")];
+		assert_eq!(metrics.histogram("span_duration_seconds", &labels).len(), 1);
+		assert_eq!(metrics.counter("span_errors_total", &labels), 1);
+		assert_eq!(
+			metrics
+				.histogram("span_duration_seconds", &[("span", "plain")])
+				.len(),
+			1
+		);
+		assert_eq!(
+			metrics.counter("span_errors_total", &[("span", "plain")]),
+			0
+		);
+	}
+}
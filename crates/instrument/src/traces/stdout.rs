@@ -0,0 +1,330 @@
+use futures::future::{self, BoxFuture};
+use opentelemetry::sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry::trace::{SpanId, SpanKind, Status, TraceId};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::time::{Duration, Instant};
+
+/// Time a trace waits for its root before it's printed as incomplete
+const STALE_AFTER: Duration = Duration::from_secs(60);
+/// Spans waiting for their root, the oldest traces are printed as incomplete beyond it
+const MAX_PENDING: usize = 10_000;
+
+/// Prints traces as trees of spans once their local root ends
+///
+/// Server and consumer spans are roots as well when their parent isn't among the spans received
+/// for their trace, which continue a remote one. A trace crossing the same process twice is only
+/// printed as one tree when its spans are exported together. Traces whose root was dropped or
+/// never ends are printed as incomplete once stale, checked whenever spans come in
+#[derive(Debug)]
+pub struct StdoutExporter {
+	pending: HashMap<TraceId, Pending>,
+	// spans across all pending traces
+	spans: usize,
+	stale_after: Duration,
+	max_pending: usize,
+}
+
+#[derive(Debug)]
+struct Pending {
+	since: Instant,
+	spans: Vec<SpanData>,
+}
+
+impl Default for StdoutExporter {
+	fn default() -> Self {
+		StdoutExporter {
+			pending: HashMap::new(),
+			spans: 0,
+			stale_after: STALE_AFTER,
+			max_pending: MAX_PENDING,
+		}
+	}
+}
+
+impl StdoutExporter {
+	fn print(&self, spans: &[SpanData], roots: &[&SpanData], incomplete: bool) {
+		let suffix = if incomplete { " (incomplete)" } else { "" };
+
+		let mut output = String::new();
+		for root in roots {
+			let _ = writeln!(output, "trace {}{}", root.span_context.trace_id(), suffix);
+			tree(&mut output, spans, root, "", "");
+		}
+
+		let _ = std::io::stdout().lock().write_all(output.as_bytes());
+	}
+
+	fn take(&mut self, trace_id: &TraceId) -> Option<Vec<SpanData>> {
+		let pending = self.pending.remove(trace_id)?;
+		self.spans -= pending.spans.len();
+
+		Some(pending.spans)
+	}
+
+	// Prints what arrived of the trace as a forest, as its root is gone or still open
+	fn flush(&mut self, trace_id: &TraceId) {
+		let Some(spans) = self.take(trace_id) else {
+			return;
+		};
+
+		let ids: Vec<SpanId> = spans
+			.iter()
+			.map(|span| span.span_context.span_id())
+			.collect();
+		let roots: Vec<&SpanData> = spans
+			.iter()
+			.filter(|span| !ids.contains(&span.parent_span_id))
+			.collect();
+
+		self.print(&spans, &roots, true);
+	}
+
+	fn flush_stale(&mut self, now: Instant) {
+		let stale: Vec<TraceId> = self
+			.pending
+			.iter()
+			.filter(|(_, pending)| now.duration_since(pending.since) >= self.stale_after)
+			.map(|(trace_id, _)| *trace_id)
+			.collect();
+		for trace_id in stale {
+			self.flush(&trace_id);
+		}
+
+		while self.spans > self.max_pending {
+			let oldest = self
+				.pending
+				.iter()
+				.min_by_key(|(_, pending)| pending.since)
+				.map(|(trace_id, _)| *trace_id);
+
+			match oldest {
+				Some(trace_id) => self.flush(&trace_id),
+				None => break,
+			}
+		}
+	}
+}
+
+impl SpanExporter for StdoutExporter {
+	fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+		let now = Instant::now();
+		let mut received = Vec::new();
+		for span in batch {
+			let trace_id = span.span_context.trace_id();
+			if !received.contains(&trace_id) {
+				received.push(trace_id);
+			}
+
+			self.spans += 1;
+			self.pending
+				.entry(trace_id)
+				.or_insert_with(|| Pending {
+					since: now,
+					spans: Vec::new(),
+				})
+				.spans
+				.push(span);
+		}
+
+		for trace_id in received {
+			let spans = &self.pending[&trace_id].spans;
+			let finished = spans.iter().any(|span| is_root(span, spans));
+
+			if finished {
+				if let Some(spans) = self.take(&trace_id) {
+					let roots: Vec<&SpanData> =
+						spans.iter().filter(|span| is_root(span, &spans)).collect();
+					self.print(&spans, &roots, false);
+				}
+			}
+		}
+
+		self.flush_stale(now);
+
+		Box::pin(future::ready(Ok(())))
+	}
+
+	fn shutdown(&mut self) {
+		let trace_ids: Vec<TraceId> = self.pending.keys().copied().collect();
+		for trace_id in trace_ids {
+			self.flush(&trace_id);
+		}
+	}
+}
+
+fn is_root(span: &SpanData, trace: &[SpanData]) -> bool {
+	let parent = span.parent_span_id;
+	if parent == SpanId::INVALID {
+		return true;
+	}
+
+	matches!(span.span_kind, SpanKind::Server | SpanKind::Consumer)
+		&& !trace
+			.iter()
+			.any(|other| other.span_context.span_id() == parent)
+}
+
+fn tree(output: &mut String, spans: &[SpanData], span: &SpanData, prefix: &str, indent: &str) {
+	let _ = writeln!(output, "{}{}", prefix, describe(span));
+
+	let span_id = span.span_context.span_id();
+	let mut children: Vec<&SpanData> = spans
+		.iter()
+		.filter(|child| child.parent_span_id == span_id)
+		.collect();
+	children.sort_by_key(|child| child.start_time);
+
+	let count = children.len();
+	for (index, child) in children.into_iter().enumerate() {
+		let last = index + 1 == count;
+		let (branch, next) = if last {
+			("└── ", "    ")
+		} else {
+			("├── ", "│   ")
+		};
+
+		let prefix = format!("{}{}", indent, branch);
+		let indent = format!("{}{}", indent, next);
+		tree(output, spans, child, &prefix, &indent);
+	}
+}
+
+fn describe(span: &SpanData) -> String {
+	let duration = span
+		.end_time
+		.duration_since(span.start_time)
+		.unwrap_or_default();
+
+	let kind = match span.span_kind {
+		SpanKind::Internal => "internal",
+		SpanKind::Server => "server",
+		SpanKind::Client => "client",
+		SpanKind::Producer => "producer",
+		SpanKind::Consumer => "consumer",
+	};
+
+	let status = match &span.status {
+		Status::Error { description } if description.is_empty() => " ERROR".to_string(),
+		Status::Error { description } => format!(" ERROR: {}", description),
+		_ => String::new(),
+	};
+
+	format!(
+		"{} [{}] {:.3}ms{}",
+		span.name,
+		kind,
+		duration.as_secs_f64() * 1000.0,
+		status
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::Collect;
+	use futures::executor::block_on;
+	use opentelemetry::sdk::trace::TracerProvider;
+	use opentelemetry::trace::{
+		SpanContext, TraceContextExt, TraceFlags, TraceState, Tracer, TracerProvider as _,
+	};
+	use opentelemetry::Context;
+
+	#[test]
+	fn roots_are_spans_without_a_local_parent() {
+		let collect = Collect::default();
+		let provider = TracerProvider::builder()
+			.with_span_processor(collect.clone())
+			.build();
+		let tracer = provider.tracer("test");
+
+		let remote = SpanContext::new(
+			TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+			SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+			TraceFlags::SAMPLED,
+			true,
+			TraceState::default(),
+		);
+		let remote = Context::new().with_remote_span_context(remote);
+		let server = tracer
+			.span_builder("server")
+			.with_kind(SpanKind::Server)
+			.start_with_context(&tracer, &remote);
+		let server = remote.with_span(server);
+		let client = tracer.start_with_context("client", &server);
+		let client = server.with_span(client);
+		let nested = tracer
+			.span_builder("nested server")
+			.with_kind(SpanKind::Server)
+			.start_with_context(&tracer, &client);
+
+		drop(nested);
+		drop(client);
+		drop(server);
+
+		let spans = collect.spans();
+		let roots: Vec<&str> = spans
+			.iter()
+			.filter(|span| is_root(span, &spans))
+			.map(|span| span.name.as_ref())
+			.collect();
+
+		assert_eq!(roots, ["server"]);
+		assert!(
+			is_root(&spans[0], &spans[..1]),
+			"exported without its parent"
+		);
+	}
+
+	// Span of `trace` whose parent never arrives
+	fn orphan(trace: &str) -> Vec<SpanData> {
+		crate::otlp_json::decode(&serde_json::json!({
+			"resourceSpans": [{
+				"scopeSpans": [{
+					"spans": [{
+						"traceId": trace,
+						"spanId": "b7ad6b7169203331",
+						"parentSpanId": "00f067aa0ba902b7",
+						"name": "orphan",
+						"startTimeUnixNano": "1000",
+						"endTimeUnixNano": "2000",
+					}],
+				}],
+			}],
+		}))
+	}
+
+	#[test]
+	fn traces_without_their_root_are_flushed_once_stale() {
+		let first = "4bf92f3577b34da6a3ce929d0e0e4736";
+		let second = "0af7651916cd43dd8448eb211c80319c";
+
+		let mut exporter = StdoutExporter {
+			max_pending: 1,
+			..Default::default()
+		};
+		block_on(exporter.export(orphan(first))).unwrap();
+		assert_eq!(exporter.pending.len(), 1, "waits for the root");
+
+		for pending in exporter.pending.values_mut() {
+			pending.since -= Duration::from_secs(1);
+		}
+		block_on(exporter.export(orphan(second))).unwrap();
+		let pending: Vec<TraceId> = exporter.pending.keys().copied().collect();
+		assert_eq!(
+			pending,
+			[TraceId::from_hex(second).unwrap()],
+			"the oldest trace makes room"
+		);
+		assert_eq!(exporter.spans, 1);
+
+		let mut exporter = StdoutExporter {
+			stale_after: Duration::ZERO,
+			..Default::default()
+		};
+		block_on(exporter.export(orphan(first))).unwrap();
+		assert!(exporter.pending.is_empty(), "old traces are flushed");
+		assert_eq!(exporter.spans, 0);
+	}
+}
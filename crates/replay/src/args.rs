@@ -0,0 +1,59 @@
+use clap::Parser;
+use std::path::PathBuf;
+
+/// Replays OTLP-JSON files, one export request per line, into an OTLP endpoint
+#[derive(Debug, Parser)]
+#[command(name = "replay")]
+pub struct Args {
+	/// OTLP gRPC endpoint
+	#[arg(long, value_name = "URL", default_value = "http://localhost:4317")]
+	pub endpoint: String,
+	/// Spans and log records sent per second [default: unlimited]
+	#[arg(long, value_name = "N", value_parser = positive)]
+	pub rate: Option<f64>,
+	/// Shift timestamps so the latest one becomes the current time
+	#[arg(long)]
+	pub now: bool,
+	/// Override `service.name` on every resource
+	#[arg(long, value_name = "NAME")]
+	pub service: Option<String>,
+	/// Files to replay, in order
+	#[arg(value_name = "FILE", required = true)]
+	pub files: Vec<PathBuf>,
+}
+
+pub fn parse() -> Args {
+	Args::parse()
+}
+
+fn positive(value: &str) -> Result<f64, String> {
+	value
+		.parse()
+		.ok()
+		.filter(|rate: &f64| *rate > 0.0)
+		.ok_or_else(|| String::from("should be a positive number"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use clap::CommandFactory;
+
+	#[test]
+	fn parses_flags_and_files() {
+		Args::command().debug_assert();
+
+		let args =
+			Args::try_parse_from(["replay", "--rate", "2.5", "--now", "a.json", "b.json"]).unwrap();
+		assert_eq!(args.endpoint, "http://localhost:4317");
+		assert_eq!(args.rate, Some(2.5));
+		assert!(args.now);
+		assert_eq!(
+			args.files,
+			[PathBuf::from("a.json"), PathBuf::from("b.json")]
+		);
+
+		assert!(Args::try_parse_from(["replay"]).is_err());
+		assert!(Args::try_parse_from(["replay", "--rate", "0", "a.json"]).is_err());
+	}
+}
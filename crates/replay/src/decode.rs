@@ -0,0 +1,340 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use instrument::otlp_json;
+use opentelemetry::trace::{SpanId, TraceId};
+use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+use opentelemetry_proto::tonic::common::v1::{
+	any_value, AnyValue, ArrayValue, InstrumentationLibrary, KeyValue, KeyValueList,
+};
+use opentelemetry_proto::tonic::logs::v1::{InstrumentationLibraryLogs, LogRecord, ResourceLogs};
+use opentelemetry_proto::tonic::resource::v1::Resource;
+use opentelemetry_proto::tonic::trace::v1::span::{Event, Link};
+use opentelemetry_proto::tonic::trace::v1::{
+	InstrumentationLibrarySpans, ResourceSpans, Span, Status,
+};
+use serde_json::Value as Json;
+
+/// Export request read from a line of OTLP-JSON
+pub enum Request {
+	Traces(ExportTraceServiceRequest),
+	Logs(ExportLogsServiceRequest),
+}
+
+/// Decodes a line holding either `resourceSpans` or `resourceLogs`
+pub fn line(line: &str) -> Result<Request, String> {
+	let json: Json = serde_json::from_str(line).map_err(|err| err.to_string())?;
+
+	if let Some(resources) = json.get("resourceSpans") {
+		let resource_spans = list(resources).iter().map(resource_spans).collect();
+
+		Ok(Request::Traces(ExportTraceServiceRequest {
+			resource_spans,
+		}))
+	} else if let Some(resources) = json.get("resourceLogs") {
+		let resource_logs = list(resources).iter().map(resource_logs).collect();
+
+		Ok(Request::Logs(ExportLogsServiceRequest { resource_logs }))
+	} else {
+		Err(String::from("expected resourceSpans or resourceLogs"))
+	}
+}
+
+fn resource_spans(json: &Json) -> ResourceSpans {
+	let scopes = either(json, "scopeSpans", "instrumentationLibrarySpans");
+
+	ResourceSpans {
+		resource: json.get("resource").map(resource),
+		instrumentation_library_spans: list(scopes)
+			.iter()
+			.map(|scope| InstrumentationLibrarySpans {
+				instrumentation_library: library(scope),
+				spans: list(&scope["spans"]).iter().map(span).collect(),
+				schema_url: string(&scope["schemaUrl"]),
+			})
+			.collect(),
+		schema_url: string(&json["schemaUrl"]),
+	}
+}
+
+fn resource_logs(json: &Json) -> ResourceLogs {
+	let scopes = either(json, "scopeLogs", "instrumentationLibraryLogs");
+
+	ResourceLogs {
+		resource: json.get("resource").map(resource),
+		instrumentation_library_logs: list(scopes)
+			.iter()
+			.map(|scope| InstrumentationLibraryLogs {
+				instrumentation_library: library(scope),
+				log_records: list(&scope["logRecords"]).iter().map(log).collect(),
+				schema_url: string(&scope["schemaUrl"]),
+			})
+			.collect(),
+		schema_url: string(&json["schemaUrl"]),
+	}
+}
+
+fn resource(json: &Json) -> Resource {
+	Resource {
+		attributes: attributes(&json["attributes"]),
+		dropped_attributes_count: otlp_json::decode_number(&json["droppedAttributesCount"]) as u32,
+	}
+}
+
+fn library(json: &Json) -> Option<InstrumentationLibrary> {
+	let library = either(json, "scope", "instrumentationLibrary");
+
+	library.as_object().map(|_| InstrumentationLibrary {
+		name: string(&library["name"]),
+		version: string(&library["version"]),
+	})
+}
+
+fn span(json: &Json) -> Span {
+	let number = |key: &str| otlp_json::decode_number(&json[key]);
+
+	Span {
+		trace_id: trace_id(&json["traceId"]),
+		span_id: span_id(&json["spanId"]),
+		trace_state: string(&json["traceState"]),
+		parent_span_id: span_id(&json["parentSpanId"]),
+		name: string(&json["name"]),
+		kind: number("kind") as i32,
+		start_time_unix_nano: number("startTimeUnixNano"),
+		end_time_unix_nano: number("endTimeUnixNano"),
+		attributes: attributes(&json["attributes"]),
+		dropped_attributes_count: number("droppedAttributesCount") as u32,
+		events: list(&json["events"]).iter().map(event).collect(),
+		dropped_events_count: number("droppedEventsCount") as u32,
+		links: list(&json["links"]).iter().map(link).collect(),
+		dropped_links_count: number("droppedLinksCount") as u32,
+		status: json.get("status").map(|status| Status {
+			message: string(&status["message"]),
+			code: otlp_json::decode_number(&status["code"]) as i32,
+		}),
+	}
+}
+
+fn event(json: &Json) -> Event {
+	Event {
+		time_unix_nano: otlp_json::decode_number(&json["timeUnixNano"]),
+		name: string(&json["name"]),
+		attributes: attributes(&json["attributes"]),
+		dropped_attributes_count: otlp_json::decode_number(&json["droppedAttributesCount"]) as u32,
+	}
+}
+
+fn link(json: &Json) -> Link {
+	Link {
+		trace_id: trace_id(&json["traceId"]),
+		span_id: span_id(&json["spanId"]),
+		trace_state: string(&json["traceState"]),
+		attributes: attributes(&json["attributes"]),
+		dropped_attributes_count: otlp_json::decode_number(&json["droppedAttributesCount"]) as u32,
+	}
+}
+
+fn log(json: &Json) -> LogRecord {
+	let number = |key: &str| otlp_json::decode_number(&json[key]);
+
+	LogRecord {
+		time_unix_nano: number("timeUnixNano"),
+		observed_time_unix_nano: number("observedTimeUnixNano"),
+		severity_number: number("severityNumber") as i32,
+		severity_text: string(&json["severityText"]),
+		body: json.get("body").map(value),
+		attributes: attributes(&json["attributes"]),
+		dropped_attributes_count: number("droppedAttributesCount") as u32,
+		flags: number("flags") as u32,
+		trace_id: trace_id(&json["traceId"]),
+		span_id: span_id(&json["spanId"]),
+		..Default::default()
+	}
+}
+
+fn attributes(json: &Json) -> Vec<KeyValue> {
+	list(json)
+		.iter()
+		.map(|attribute| KeyValue {
+			key: string(&attribute["key"]),
+			value: attribute.get("value").map(value),
+		})
+		.collect()
+}
+
+// Unlike the SDK types the files are written from, keeps key-value lists and bytes
+fn value(json: &Json) -> AnyValue {
+	let value = if let Some(value) = json.get("stringValue") {
+		Some(any_value::Value::StringValue(string(value)))
+	} else if let Some(value) = json.get("boolValue") {
+		value.as_bool().map(any_value::Value::BoolValue)
+	} else if let Some(value) = json.get("intValue") {
+		match value {
+			Json::String(value) => value.parse().ok(),
+			value => value.as_i64(),
+		}
+		.map(any_value::Value::IntValue)
+	} else if let Some(value) = json.get("doubleValue") {
+		value.as_f64().map(any_value::Value::DoubleValue)
+	} else if let Some(value) = json.get("arrayValue") {
+		Some(any_value::Value::ArrayValue(ArrayValue {
+			values: list(&value["values"]).iter().map(self::value).collect(),
+		}))
+	} else if let Some(value) = json.get("kvlistValue") {
+		Some(any_value::Value::KvlistValue(KeyValueList {
+			values: attributes(&value["values"]),
+		}))
+	} else if let Some(value) = json.get("bytesValue") {
+		value
+			.as_str()
+			.and_then(|bytes| BASE64.decode(bytes).ok())
+			.map(any_value::Value::BytesValue)
+	} else {
+		None
+	};
+
+	AnyValue { value }
+}
+
+fn either<'a>(json: &'a Json, current: &str, legacy: &str) -> &'a Json {
+	json.get(current).unwrap_or(&json[legacy])
+}
+
+fn list(json: &Json) -> &[Json] {
+	json.as_array().map(Vec::as_slice).unwrap_or_default()
+}
+
+fn string(json: &Json) -> String {
+	json.as_str().unwrap_or_default().to_string()
+}
+
+fn trace_id(json: &Json) -> Vec<u8> {
+	match json.as_str().map(TraceId::from_hex) {
+		Some(Ok(id)) if id != TraceId::INVALID => id.to_bytes().to_vec(),
+		_ => Vec::new(),
+	}
+}
+
+fn span_id(json: &Json) -> Vec<u8> {
+	match json.as_str().map(SpanId::from_hex) {
+		Some(Ok(id)) if id != SpanId::INVALID => id.to_bytes().to_vec(),
+		_ => Vec::new(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn spans_keep_ids_and_timestamps() {
+		let json = r#"{"resourceSpans":[{"resource":{"attributes":[{"key":"service.name","value":{"stringValue":"gollum"}}]},"scopeSpans":[{"scope":{"name":"instrument","version":"0.0.0"},"spans":[{"traceId":"4bf92f3577b34da6a3ce929d0e0e4736","spanId":"00f067aa0ba902b7","parentSpanId":"","name":"GET /hello","kind":2,"startTimeUnixNano":"1792346555061288746","endTimeUnixNano":"1792346555062220024","attributes":[{"key":"code.lineno","value":{"intValue":"43"}}],"status":{"code":1,"message":""}}]}]}]}"#;
+
+		let request = match line(json).unwrap() {
+			Request::Traces(request) => request,
+			Request::Logs(_) => panic!("expected traces"),
+		};
+
+		let scope = &request.resource_spans[0].instrumentation_library_spans[0];
+		let span = &scope.spans[0];
+		assert_eq!(
+			scope.instrumentation_library.as_ref().unwrap().name,
+			"instrument"
+		);
+		assert_eq!(span.trace_id.len(), 16);
+		assert_eq!(span.span_id.len(), 8);
+		assert!(span.parent_span_id.is_empty());
+		assert_eq!(span.start_time_unix_nano, 1792346555061288746);
+		assert_eq!(
+			span.attributes[0].value,
+			Some(AnyValue {
+				value: Some(any_value::Value::IntValue(43))
+			})
+		);
+	}
+
+	#[test]
+	fn spans_keep_what_the_sdk_has_no_type_for() {
+		let json = r#"{"resourceSpans":[{"resource":{"attributes":[{"key":"service.name","value":{"stringValue":"gollum"}}]},"scopeSpans":[{"spans":[{"traceId":"4bf92f3577b34da6a3ce929d0e0e4736","spanId":"00f067aa0ba902b7","attributes":[{"key":"user","value":{"kvlistValue":{"values":[{"key":"id","value":{"intValue":"7"}}]}}},{"key":"digest","value":{"bytesValue":"3q2+7w=="}}],"droppedAttributesCount":2,"events":[{"name":"retry","droppedAttributesCount":1}],"droppedEventsCount":3,"droppedLinksCount":4},{"traceId":"4bf92f3577b34da6a3ce929d0e0e4736","spanId":"00f067aa0ba902b8"}]}]}]}"#;
+
+		let request = match line(json).unwrap() {
+			Request::Traces(request) => request,
+			Request::Logs(_) => panic!("expected traces"),
+		};
+
+		assert_eq!(
+			request.resource_spans.len(),
+			1,
+			"spans share their resource"
+		);
+		let spans = &request.resource_spans[0].instrumentation_library_spans[0].spans;
+		assert_eq!(spans.len(), 2);
+
+		let span = &spans[0];
+		assert_eq!(
+			span.attributes,
+			vec![
+				KeyValue {
+					key: String::from("user"),
+					value: Some(AnyValue {
+						value: Some(any_value::Value::KvlistValue(KeyValueList {
+							values: vec![KeyValue {
+								key: String::from("id"),
+								value: Some(AnyValue {
+									value: Some(any_value::Value::IntValue(7))
+								}),
+							}],
+						})),
+					}),
+				},
+				KeyValue {
+					key: String::from("digest"),
+					value: Some(AnyValue {
+						value: Some(any_value::Value::BytesValue(vec![0xde, 0xad, 0xbe, 0xef])),
+					}),
+				},
+			]
+		);
+		assert_eq!(span.dropped_attributes_count, 2);
+		assert_eq!(span.events[0].dropped_attributes_count, 1);
+		assert_eq!(span.dropped_events_count, 3);
+		assert_eq!(span.dropped_links_count, 4);
+	}
+
+	#[test]
+	fn logs_keep_their_scope_and_fields() {
+		let json = r#"{"resourceLogs":[{"resource":{"attributes":[{"key":"service.name","value":{"stringValue":"gollum"}}]},"scopeLogs":[{"scope":{"name":"instrument"},"logRecords":[{"timeUnixNano":"1792346555061288746","severityNumber":9,"severityText":"INFO","body":{"stringValue":"hello"},"attributes":[{"key":"code.lineno","value":{"intValue":"43"}}],"traceId":"4bf92f3577b34da6a3ce929d0e0e4736","spanId":"00f067aa0ba902b7"}]}]}]}"#;
+
+		let request = match line(json).unwrap() {
+			Request::Logs(request) => request,
+			Request::Traces(_) => panic!("expected logs"),
+		};
+
+		let resource = &request.resource_logs[0];
+		assert_eq!(
+			resource.resource.as_ref().unwrap().attributes[0].key,
+			"service.name"
+		);
+
+		let scope = &resource.instrumentation_library_logs[0];
+		let log = &scope.log_records[0];
+		assert_eq!(
+			scope.instrumentation_library.as_ref().unwrap().name,
+			"instrument"
+		);
+		assert_eq!(log.time_unix_nano, 1792346555061288746);
+		assert_eq!(log.severity_number, 9);
+		assert_eq!(log.severity_text, "INFO");
+		assert_eq!(
+			log.body,
+			Some(AnyValue {
+				value: Some(any_value::Value::StringValue(String::from("hello")))
+			})
+		);
+		assert_eq!(log.attributes[0].key, "code.lineno");
+		assert_eq!(log.trace_id.len(), 16);
+		assert_eq!(log.span_id.len(), 8);
+
+		assert!(line(r#"{"resourceMetrics":[]}"#).is_err());
+	}
+}
@@ -0,0 +1,88 @@
+mod args;
+mod decode;
+mod rewrite;
+
+use decode::Request;
+use opentelemetry_proto::tonic::collector::logs::v1::logs_service_client::LogsServiceClient;
+use opentelemetry_proto::tonic::collector::trace::v1::trace_service_client::TraceServiceClient;
+use std::fs;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tonic::transport::Channel;
+
+#[tokio::main]
+async fn main() {
+	let args = args::parse();
+
+	let mut requests = Vec::new();
+	for file in &args.files {
+		let content = fs::read_to_string(file)
+			.unwrap_or_else(|err| fail(format!("Unable to read {}: {}", file.display(), err)));
+
+		for (index, line) in content.lines().enumerate() {
+			if line.trim().is_empty() {
+				continue;
+			}
+
+			let request = decode::line(line).unwrap_or_else(|err| {
+				fail(format!(
+					"Invalid OTLP-JSON at {}:{}: {}",
+					file.display(),
+					index + 1,
+					err
+				))
+			});
+			requests.push(request);
+		}
+	}
+
+	if args.now {
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.expect("System clock is before the epoch")
+			.as_nanos() as u64;
+
+		rewrite::align(&mut requests, now);
+	}
+
+	if let Some(service) = &args.service {
+		for request in &mut requests {
+			rewrite::service(request, service);
+		}
+	}
+
+	let channel = Channel::from_shared(args.endpoint.clone())
+		.unwrap_or_else(|err| fail(format!("Invalid OTLP endpoint {}: {}", args.endpoint, err)))
+		.connect()
+		.await
+		.unwrap_or_else(|err| fail(format!("Unable to connect to {}: {}", args.endpoint, err)));
+	let mut traces = TraceServiceClient::new(channel.clone());
+	let mut logs = LogsServiceClient::new(channel);
+
+	let mut sent = 0;
+	for request in requests {
+		let records = rewrite::records(&request);
+
+		match request {
+			Request::Traces(request) => traces.export(request).await.map(|_| ()),
+			Request::Logs(request) => logs.export(request).await.map(|_| ()),
+		}
+		.unwrap_or_else(|status| {
+			fail(format!("{} rejected the export: {}", args.endpoint, status))
+		});
+
+		sent += records;
+
+		if let Some(rate) = args.rate {
+			tokio::time::sleep(Duration::from_secs_f64(records as f64 / rate)).await;
+		}
+	}
+
+	println!("Replayed {} records to {}", sent, args.endpoint);
+}
+
+/// Reports `message` and exits with a failure, sparing users the panic output for bad input
+fn fail(message: String) -> ! {
+	eprintln!("{}", message);
+	process::exit(1)
+}
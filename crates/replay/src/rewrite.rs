@@ -0,0 +1,203 @@
+use crate::decode::Request;
+use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, KeyValue};
+use opentelemetry_proto::tonic::resource::v1::Resource;
+
+/// Number of spans and log records in `request`
+pub fn records(request: &Request) -> usize {
+	match request {
+		Request::Traces(traces) => traces
+			.resource_spans
+			.iter()
+			.flat_map(|resource| &resource.instrumentation_library_spans)
+			.map(|scope| scope.spans.len())
+			.sum(),
+		Request::Logs(logs) => logs
+			.resource_logs
+			.iter()
+			.flat_map(|resource| &resource.instrumentation_library_logs)
+			.map(|scope| scope.log_records.len())
+			.sum(),
+	}
+}
+
+/// Shifts the timestamps of all `requests` so the latest one becomes `now`, in nanoseconds since
+/// the epoch
+///
+/// A single offset for all requests keeps spans of the same trace aligned across lines
+pub fn align(requests: &mut [Request], now: u64) {
+	let offset = now as i128 - latest(requests) as i128;
+
+	for request in requests {
+		shift(request, offset);
+	}
+}
+
+/// Latest timestamp found in `requests`, in nanoseconds since the epoch
+fn latest(requests: &[Request]) -> u64 {
+	let mut latest = 0;
+	for request in requests {
+		visit_timestamps(request, |timestamp| latest = latest.max(timestamp));
+	}
+
+	latest
+}
+
+/// Moves every timestamp in `request` by `offset` nanoseconds, leaving unset ones alone
+fn shift(request: &mut Request, offset: i128) {
+	let shift = |timestamp: &mut u64| {
+		if *timestamp != 0 {
+			*timestamp = (*timestamp as i128 + offset).max(0) as u64;
+		}
+	};
+
+	match request {
+		Request::Traces(traces) => {
+			for resource in &mut traces.resource_spans {
+				for scope in &mut resource.instrumentation_library_spans {
+					for span in &mut scope.spans {
+						shift(&mut span.start_time_unix_nano);
+						shift(&mut span.end_time_unix_nano);
+						for event in &mut span.events {
+							shift(&mut event.time_unix_nano);
+						}
+					}
+				}
+			}
+		}
+		Request::Logs(logs) => {
+			for resource in &mut logs.resource_logs {
+				for scope in &mut resource.instrumentation_library_logs {
+					for log in &mut scope.log_records {
+						shift(&mut log.time_unix_nano);
+						shift(&mut log.observed_time_unix_nano);
+					}
+				}
+			}
+		}
+	}
+}
+
+/// Replaces `service.name` on every resource of `request`, adding it where missing
+pub fn service(request: &mut Request, name: &str) {
+	let resources: Vec<&mut Option<Resource>> = match request {
+		Request::Traces(traces) => traces
+			.resource_spans
+			.iter_mut()
+			.map(|resource| &mut resource.resource)
+			.collect(),
+		Request::Logs(logs) => logs
+			.resource_logs
+			.iter_mut()
+			.map(|resource| &mut resource.resource)
+			.collect(),
+	};
+
+	for resource in resources {
+		let attributes = &mut resource.get_or_insert_with(Default::default).attributes;
+		attributes.retain(|attribute| attribute.key != "service.name");
+		attributes.push(KeyValue {
+			key: String::from("service.name"),
+			value: Some(AnyValue {
+				value: Some(any_value::Value::StringValue(name.to_string())),
+			}),
+		});
+	}
+}
+
+fn visit_timestamps(request: &Request, mut visit: impl FnMut(u64)) {
+	match request {
+		Request::Traces(traces) => {
+			for resource in &traces.resource_spans {
+				for scope in &resource.instrumentation_library_spans {
+					for span in &scope.spans {
+						visit(span.start_time_unix_nano);
+						visit(span.end_time_unix_nano);
+						span.events
+							.iter()
+							.for_each(|event| visit(event.time_unix_nano));
+					}
+				}
+			}
+		}
+		Request::Logs(logs) => {
+			for resource in &logs.resource_logs {
+				for scope in &resource.instrumentation_library_logs {
+					for log in &scope.log_records {
+						visit(log.time_unix_nano);
+						visit(log.observed_time_unix_nano);
+					}
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::decode;
+	use opentelemetry_proto::tonic::trace::v1::ResourceSpans;
+
+	fn traces(request: &Request) -> &[ResourceSpans] {
+		match request {
+			Request::Traces(traces) => &traces.resource_spans,
+			Request::Logs(_) => panic!("expected traces"),
+		}
+	}
+
+	fn service_names(resource: &Option<Resource>) -> Vec<&AnyValue> {
+		resource
+			.iter()
+			.flat_map(|resource| &resource.attributes)
+			.filter(|attribute| attribute.key == "service.name")
+			.filter_map(|attribute| attribute.value.as_ref())
+			.collect()
+	}
+
+	#[test]
+	fn aligns_the_latest_timestamp_with_now() {
+		let mut requests = [
+			decode::line(r#"{"resourceSpans":[{"scopeSpans":[{"spans":[{"traceId":"4bf92f3577b34da6a3ce929d0e0e4736","spanId":"00f067aa0ba902b7","startTimeUnixNano":"1000","endTimeUnixNano":"3000"}]}]}]}"#).unwrap(),
+			decode::line(r#"{"resourceLogs":[{"scopeLogs":[{"logRecords":[{"timeUnixNano":"2000","observedTimeUnixNano":"0"}]}]}]}"#).unwrap(),
+		];
+
+		align(&mut requests, 10_000);
+
+		let span = &traces(&requests[0])[0].instrumentation_library_spans[0].spans[0];
+		assert_eq!(span.start_time_unix_nano, 8_000);
+		assert_eq!(span.end_time_unix_nano, 10_000);
+
+		let Request::Logs(logs) = &requests[1] else {
+			panic!("expected logs");
+		};
+		let log = &logs.resource_logs[0].instrumentation_library_logs[0].log_records[0];
+		assert_eq!(log.time_unix_nano, 9_000, "requests share the offset");
+		assert_eq!(
+			log.observed_time_unix_nano, 0,
+			"unset timestamps stay unset"
+		);
+	}
+
+	#[test]
+	fn overrides_the_service_name() {
+		let mut request = decode::line(r#"{"resourceSpans":[{"resource":{"attributes":[{"key":"service.name","value":{"stringValue":"gollum"}},{"key":"host.name","value":{"stringValue":"mordor"}}]},"scopeSpans":[{"spans":[{"traceId":"4bf92f3577b34da6a3ce929d0e0e4736","spanId":"00f067aa0ba902b7"}]}]},{"scopeSpans":[{"spans":[{"traceId":"4bf92f3577b34da6a3ce929d0e0e4736","spanId":"00f067aa0ba902b8"}]}]}]}"#).unwrap();
+
+		service(&mut request, "smeagol");
+
+		let smeagol = AnyValue {
+			value: Some(any_value::Value::StringValue(String::from("smeagol"))),
+		};
+		let resources = traces(&request);
+		assert_eq!(service_names(&resources[0].resource), [&smeagol]);
+		assert_eq!(
+			resources[0].resource.as_ref().unwrap().attributes.len(),
+			2,
+			"other attributes are kept"
+		);
+		assert_eq!(
+			service_names(&resources[1].resource),
+			[&smeagol],
+			"resources without a name get one"
+		);
+	}
+}